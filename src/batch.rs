@@ -0,0 +1,279 @@
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+#[cfg(not(feature = "disable"))]
+use super::math;
+use super::{add_f64, add_sum, update_f64, Histo, Scale, Tracked};
+#[cfg(not(feature = "disable"))]
+use alloc::vec;
+
+/// A buffer of measurements in plain memory, created by `Histo::batch`.
+/// Adding values performs no atomic operations, and the buffered
+/// values are added to a `Histo` in a single pass by `Histo::apply_batch`.
+/// Unlike `LocalHisto`, it does not borrow the histogram, so the caller
+/// decides where it lives and when it is applied.
+pub struct Batch {
+    scale: Scale,
+    tracked: Tracked,
+    // allocated on the first `add`, so that short-lived batches and idle
+    // `LocalHisto`s do not pay for a full set of buckets
+    vals: Vec<usize>,
+    // the half-open range of `vals` that may be nonzero, so that
+    // `Histo::apply_batch` only scans the buckets that were touched
+    low: usize,
+    high: usize,
+    sum: u64,
+    residual: f64,
+    log_sum: f64,
+    log_count: u64,
+    sum_sq: f64,
+    sq_count: u64,
+    count: u64,
+    clamped: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Batch {
+    pub(crate) fn new(scale: Scale, tracked: Tracked) -> Batch {
+        Batch {
+            scale,
+            tracked,
+            vals: Vec::new(),
+            low: usize::MAX,
+            high: 0,
+            sum: 0,
+            residual: 0.,
+            log_sum: 0.,
+            log_count: 0,
+            sum_sq: 0.,
+            sq_count: 0,
+            count: 0,
+            clamped: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Record a value into the batch, handling NAN and infinite values
+    /// the same way as `Histo::measure`.
+    #[inline]
+    pub fn add<T: Into<f64>>(&mut self, raw_value: T) {
+        #[cfg(not(feature = "disable"))]
+        {
+            let value_float: f64 = raw_value.into();
+            if value_float.is_nan() {
+                return;
+            }
+
+            if value_float.is_finite() {
+                // like `Histo::record`, carry what would overflow the
+                // integer sum into the residual
+                let rounded = math::round(value_float) as u64;
+                match self.sum.checked_add(rounded) {
+                    Some(sum) => self.sum = sum,
+                    None => self.residual += rounded as f64,
+                }
+                self.residual += value_float - rounded as f64;
+                if self.tracked.sum_sq {
+                    self.sum_sq += value_float * value_float;
+                    self.sq_count += 1;
+                }
+            }
+            if self.tracked.log_sum && value_float > 0. && value_float.is_finite() {
+                self.log_sum += math::ln(value_float);
+                self.log_count += 1;
+            }
+            self.count += 1;
+            self.min = self.min.min(value_float);
+            self.max = self.max.max(value_float);
+
+            let compressed: u16 = match self.scale.try_compress(value_float) {
+                Some(compressed) => compressed,
+                None => {
+                    self.clamped += 1;
+                    self.scale.saturate(value_float)
+                }
+            };
+            if self.vals.is_empty() {
+                self.vals = vec![0; self.scale.buckets];
+            }
+            let idx = compressed as usize;
+            self.vals[idx] += 1;
+            self.low = self.low.min(idx);
+            self.high = self.high.max(idx + 1);
+        }
+
+        #[cfg(feature = "disable")]
+        let _ = (raw_value, self.tracked);
+    }
+
+    /// Return the number of values buffered since the batch was
+    /// created or last applied.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    // Returns the index of the bucket of `histo` that corresponds to
+    // bucket `idx` of this batch.
+    fn bucket_in(&self, histo: &Histo, idx: usize) -> usize {
+        if self.scale == histo.scale {
+            return idx;
+        }
+
+        #[cfg(not(feature = "saturating"))]
+        panic!(
+            "cannot combine histograms with different bucketing: {:?} and {:?}",
+            histo.scale, self.scale
+        );
+
+        #[cfg(feature = "saturating")]
+        {
+            let value = self.scale.decompress(idx as u16);
+            histo.scale.compress(value) as usize
+        }
+    }
+}
+
+impl Histo {
+    /// Create an empty `Batch` with the bucketing of this histogram.
+    pub fn batch(&self) -> Batch {
+        Batch::new(self.scale, self.tracked)
+    }
+
+    /// Add every value buffered in `batch` to this histogram, leaving
+    /// the batch empty so that it can be reused. Values added through a
+    /// batch are not retained by `HistoBuilder::track_top_k`.
+    ///
+    /// Panics if the batch was created by a histogram with different
+    /// bucketing, unless the `saturating` feature is enabled.
+    pub fn apply_batch(&self, batch: &mut Batch) {
+        if batch.count == 0 {
+            return;
+        }
+
+        let _guard = self.write_guard();
+
+        let mut dropped = 0;
+        for idx in batch.low..batch.high {
+            let count = batch.vals[idx];
+            if count != 0 {
+                if self.increment(batch.bucket_in(self, idx), count).is_none() {
+                    dropped += count as u64;
+                }
+                batch.vals[idx] = 0;
+            }
+        }
+
+        add_sum(&self.sum, &self.residual, batch.sum, 1);
+        if batch.residual != 0. {
+            add_f64(&self.residual, batch.residual);
+        }
+        if batch.log_count != 0 {
+            add_f64(&self.log_sum, batch.log_sum);
+            self.log_count.fetch_add(batch.log_count, Ordering::Relaxed);
+        }
+        if batch.sq_count != 0 {
+            add_f64(&self.sum_sq, batch.sum_sq);
+            self.sq_count.fetch_add(batch.sq_count, Ordering::Relaxed);
+        }
+        self.clamped.fetch_add(batch.clamped, Ordering::Relaxed);
+        self.count
+            .fetch_add(batch.count - dropped, Ordering::Relaxed);
+        update_f64(&self.min, batch.min, |new, min| new < min);
+        update_f64(&self.max, batch.max, |new, max| new > max);
+
+        batch.low = usize::MAX;
+        batch.high = 0;
+        batch.sum = 0;
+        batch.residual = 0.;
+        batch.log_sum = 0.;
+        batch.log_count = 0;
+        batch.sum_sq = 0.;
+        batch.sq_count = 0;
+        batch.count = 0;
+        batch.clamped = 0;
+        batch.min = f64::INFINITY;
+        batch.max = f64::NEG_INFINITY;
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn batch() {
+    let direct = Histo::default();
+    let c = Histo::default();
+    let mut batch = c.batch();
+
+    for i in 0..1000 {
+        batch.add(i as f64 * 1.5);
+        direct.measure(i as f64 * 1.5);
+    }
+    batch.add(f64::NAN);
+    assert_eq!(batch.count(), 1000);
+    assert_eq!(c.count(), 0);
+
+    c.apply_batch(&mut batch);
+    assert_eq!(batch.count(), 0);
+    assert_eq!(c, direct);
+    assert_eq!(c.min(), direct.min());
+    assert_eq!(c.max(), direct.max());
+    assert_eq!(
+        c.quantiles(&[0., 50., 99., 100.]),
+        direct.quantiles(&[0., 50., 99., 100.])
+    );
+
+    // applying the emptied batch again changes nothing
+    c.apply_batch(&mut batch);
+    assert_eq!(c, direct);
+
+    batch.add(7);
+    c.apply_batch(&mut batch);
+    assert_eq!(c.count(), 1001);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn batch_dirty_range() {
+    let c = Histo::default();
+    let mut batch = c.batch();
+    assert_eq!(batch.vals.capacity(), 0);
+
+    batch.add(5);
+    batch.add(1_000_000);
+    batch.add(20);
+    assert_eq!(batch.vals.len(), c.vals.len());
+    assert_eq!(batch.low, c.scale.compress(5.) as usize);
+    assert_eq!(batch.high, c.scale.compress(1_000_000.) as usize + 1);
+
+    c.apply_batch(&mut batch);
+    assert_eq!((batch.low, batch.high), (usize::MAX, 0));
+    assert!(batch.vals.iter().all(|&count| count == 0));
+    assert_eq!(c.count(), 3);
+    assert_eq!(c.max(), 1_000_000.);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn batch_large_values() {
+    for &value in &[5e9, 1e20] {
+        let builder = Histo::builder().track_variance(true);
+        let direct = builder.clone().build();
+        let c = builder.clone().build();
+        let local = builder.clone().build();
+        let mut batch = c.batch();
+        for _ in 0..2 {
+            batch.add(value);
+            direct.measure(value);
+            local.local().measure(value);
+        }
+        c.apply_batch(&mut batch);
+
+        for c in &[c, local] {
+            assert_eq!(c.sum(), direct.sum());
+            assert_eq!(c.mean(), direct.mean());
+            assert_eq!(c.variance(), direct.variance());
+            assert_eq!(*c, direct);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use core::convert::TryFrom;
+#[cfg(feature = "u16_buckets")]
+use core::sync::atomic::AtomicU16 as Atomic;
+#[cfg(all(feature = "small_buckets", not(feature = "u16_buckets")))]
+use core::sync::atomic::AtomicU32 as Atomic;
+#[cfg(not(any(feature = "small_buckets", feature = "u16_buckets")))]
+use core::sync::atomic::AtomicUsize as Atomic;
+use core::sync::atomic::Ordering;
+
+#[cfg(feature = "u16_buckets")]
+type Raw = u16;
+#[cfg(all(feature = "small_buckets", not(feature = "u16_buckets")))]
+type Raw = u32;
+#[cfg(not(any(feature = "small_buckets", feature = "u16_buckets")))]
+type Raw = usize;
+
+// Bucket is the atomic counter for a single bucket. Its width depends
+// on the enabled features, but counts are always exposed as usize.
+#[derive(Debug, Default)]
+pub(crate) struct Bucket(Atomic);
+
+// casts between Raw and usize are no-ops with the default width
+#[allow(clippy::unnecessary_cast)]
+impl Bucket {
+    // the largest count a bucket can hold
+    #[cfg(all(test, not(feature = "disable")))]
+    pub(crate) const MAX: usize = Raw::MAX as usize;
+
+    pub(crate) fn new(count: usize) -> Bucket {
+        Bucket(Atomic::new(saturate(count)))
+    }
+
+    #[inline]
+    pub(crate) fn load(&self, order: Ordering) -> usize {
+        self.0.load(order) as usize
+    }
+
+    pub(crate) fn store(&self, count: usize, order: Ordering) {
+        self.0.store(saturate(count), order)
+    }
+
+    #[inline]
+    pub(crate) fn swap(&self, count: usize, order: Ordering) -> usize {
+        self.0.swap(saturate(count), order) as usize
+    }
+
+    // Subtracts `n`, which must not exceed the current count.
+    #[inline]
+    pub(crate) fn sub(&self, n: usize) {
+        self.0.fetch_sub(saturate(n), Ordering::Relaxed);
+    }
+
+    // Adds `n` to this bucket and returns its new count, or leaves it
+    // unchanged and returns `None` if that would exceed `MAX`.
+    #[inline]
+    pub(crate) fn add(&self, n: usize) -> Option<usize> {
+        let n = Raw::try_from(n).ok()?;
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| old.checked_add(n))
+            .ok()
+            .map(|old| (old + n) as usize)
+    }
+}
+
+#[inline]
+fn saturate(count: usize) -> Raw {
+    Raw::try_from(count).unwrap_or(Raw::MAX)
+}
+
+#[cfg(all(
+    feature = "small_buckets",
+    not(feature = "u16_buckets"),
+    not(feature = "disable")
+))]
+#[test]
+fn small_buckets() {
+    use super::Histo;
+
+    assert_eq!(std::mem::size_of::<Bucket>(), 4);
+
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i);
+    }
+    assert_eq!(c.count(), 1000);
+    assert_eq!(c.percentile(50.).round() as usize, 497);
+    assert_eq!(c.measure_n(10, 1 << 32), usize::MAX);
+    assert_eq!(c.overflow_count(), 1 << 32);
+}
+
+#[cfg(all(feature = "u16_buckets", not(feature = "disable")))]
+#[test]
+fn u16_buckets() {
+    use super::Histo;
+
+    assert_eq!(std::mem::size_of::<Bucket>(), 2);
+
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i);
+    }
+    assert_eq!(c.count(), 1000);
+    assert_eq!(c.percentile(50.).round() as usize, 497);
+
+    for _ in 0..=u16::MAX {
+        c.measure(5000);
+    }
+    assert_eq!(c.overflow_count(), 1);
+    assert_eq!(c.count(), 1000 + u16::MAX as u64);
+    assert_eq!(c.measure_n(10, 1 << 16), usize::MAX);
+    assert_eq!(c.overflow_count(), 1 + (1 << 16));
+}
@@ -0,0 +1,271 @@
+use core::fmt;
+
+#[cfg(feature = "reservoir")]
+use super::reservoir::Reservoir;
+use super::seqlock::SeqLock;
+#[cfg(feature = "std")]
+use super::top::TopK;
+use super::{math, Histo, Scale, Tracked, BUCKETS, PRECISION};
+
+/// An error returned by `HistoBuilder::try_build` for an invalid
+/// configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildError {
+    /// The precision was not positive and finite.
+    Precision(f64),
+    /// The maximum number of buckets was zero, too few to preserve the
+    /// sign of values, or more than bucket indices can address.
+    Buckets(usize),
+    /// The number of values retained by `track_top_k` was zero.
+    TopK(usize),
+    /// The capacity of the `reservoir` was zero.
+    Reservoir(usize),
+    /// The minimum of the `valid_range` was above its maximum, or
+    /// either bound was NAN.
+    ValidRange(f64, f64),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Precision(precision) => {
+                write!(f, "precision must be positive and finite, got {}", precision)
+            }
+            BuildError::Buckets(buckets) => {
+                write!(f, "max_buckets must be between 1 and 2^16, got {}", buckets)
+            }
+            BuildError::TopK(k) => write!(f, "track_top_k must be positive, got {}", k),
+            BuildError::Reservoir(capacity) => {
+                write!(f, "reservoir capacity must be positive, got {}", capacity)
+            }
+            BuildError::ValidRange(min, max) => {
+                write!(f, "valid_range must have min <= max, got {} and {}", min, max)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+// Returns whether every index of a histogram with `buckets` buckets,
+// and the mirrored half of a signed one, fits in the u16 bucket indices.
+const fn valid_bucket_count(buckets: usize, signed: bool) -> bool {
+    let min = if signed { 2 } else { 1 };
+    min <= buckets && buckets <= u16::MAX as usize + 1
+}
+
+const _: () = assert!(valid_bucket_count(BUCKETS, true));
+
+/// A builder for a `Histo` with non-default bucketing.
+///
+/// Higher precision yields smaller buckets and therefore lower error,
+/// at the cost of covering a smaller range of values with the same
+/// number of buckets.
+#[derive(Debug, Clone)]
+pub struct HistoBuilder {
+    precision: f64,
+    max_buckets: usize,
+    signed: bool,
+    valid: (f64, f64),
+    tracked: Tracked,
+    consistent: bool,
+    #[cfg(feature = "std")]
+    top_k: Option<usize>,
+    #[cfg(feature = "reservoir")]
+    reservoir: Option<usize>,
+}
+
+impl Default for HistoBuilder {
+    fn default() -> HistoBuilder {
+        HistoBuilder {
+            precision: PRECISION,
+            max_buckets: BUCKETS,
+            signed: false,
+            valid: (f64::NEG_INFINITY, f64::INFINITY),
+            tracked: Tracked::default(),
+            consistent: false,
+            #[cfg(feature = "std")]
+            top_k: None,
+            #[cfg(feature = "reservoir")]
+            reservoir: None,
+        }
+    }
+}
+
+impl HistoBuilder {
+    /// Set the number of buckets per unit of `ln(1 + value)`.
+    /// Defaults to 100, which bounds the error at around 0.5%.
+    /// It must be positive and finite, which is checked by `try_build`.
+    pub fn precision(mut self, precision: f64) -> HistoBuilder {
+        self.precision = precision;
+        self
+    }
+
+    /// Set the precision so that each bucket spans a factor of `base`
+    /// in `1 + value`, such as 1.01 for buckets that are 1% wide.
+    /// It must be finite and greater than 1, otherwise `try_build`
+    /// reports the resulting precision as invalid.
+    pub fn log_base(self, base: f64) -> HistoBuilder {
+        self.precision(1. / math::ln(base))
+    }
+
+    /// Set the precision so that the error of a percentile is at most
+    /// roughly `relative_error`, such as 0.001 for 0.1%. Since values are
+    /// rounded to the nearest bucket, each bucket spans twice this.
+    /// It must be positive and finite, otherwise `try_build` reports the
+    /// resulting precision as invalid.
+    pub fn relative_error(self, relative_error: f64) -> HistoBuilder {
+        self.precision(0.5 / math::ln(1. + relative_error))
+    }
+
+    /// Set the maximum number of buckets to allocate, which may not
+    /// exceed the default of 2^16, as checked by `try_build`. Fewer
+    /// buckets are allocated if the precision does not need them to
+    /// cover every finite `f64`. Values beyond the last bucket are
+    /// clamped into it.
+    pub fn max_buckets(mut self, max_buckets: usize) -> HistoBuilder {
+        self.max_buckets = max_buckets;
+        self
+    }
+
+    /// Preserve the sign of measured values. See `Histo::signed`.
+    pub fn signed(mut self, signed: bool) -> HistoBuilder {
+        self.signed = signed;
+        self
+    }
+
+    /// Retain the `k` largest raw values measured, alongside the
+    /// buckets, for retrieval with `Histo::top_k`. This takes a lock
+    /// for values that are among the largest seen so far. `k` must be
+    /// positive, which is checked by `try_build`.
+    #[cfg(feature = "std")]
+    pub fn track_top_k(mut self, k: usize) -> HistoBuilder {
+        self.top_k = Some(k);
+        self
+    }
+
+    /// Retain a uniform random sample of up to `capacity` raw values
+    /// measured, from which `Histo::exact_percentile` computes exact
+    /// percentiles of the sample to validate the bucketed ones against.
+    /// This takes a lock for every measurement, so it is intended for
+    /// development rather than production. The sample is cleared by
+    /// `reset` and `drain`, but is not carried over by `merge`.
+    /// `capacity` must be positive, which is checked by `try_build`.
+    #[cfg(feature = "reservoir")]
+    pub fn reservoir(mut self, capacity: usize) -> HistoBuilder {
+        self.reservoir = Some(capacity);
+        self
+    }
+
+    /// Set the inclusive range of values that `Histo::observe_valid`
+    /// records, so that sentinels outside of it are ignored. Other
+    /// measurement methods are unaffected. `min` must not exceed `max`,
+    /// which is checked by `try_build`.
+    pub fn valid_range(mut self, min: f64, max: f64) -> HistoBuilder {
+        self.valid = (min, max);
+        self
+    }
+
+    /// Maintain the sum of the logarithms of positive values, so that
+    /// `Histo::geometric_mean` can be computed. This is off by default
+    /// since it takes a logarithm and an extra atomic update on every
+    /// measurement. Histograms merged into this one should track it too.
+    pub fn track_geometric_mean(mut self, track: bool) -> HistoBuilder {
+        self.tracked.log_sum = track;
+        self
+    }
+
+    /// Maintain the sum of the squares of finite values, so that
+    /// `Histo::variance` and `Histo::stddev` can be computed. This is off
+    /// by default since it takes an extra atomic update on every
+    /// measurement. Histograms merged into this one should track it too.
+    pub fn track_variance(mut self, track: bool) -> HistoBuilder {
+        self.tracked.sum_sq = track;
+        self
+    }
+
+    /// Make `Histo::snapshot` retry until it reads the buckets and
+    /// totals without any measurement or merge landing partway through,
+    /// so that they agree exactly. Each measurement then performs a few
+    /// more atomic operations, and may briefly wait for a snapshot that
+    /// has repeatedly been interrupted.
+    pub fn consistent_snapshots(mut self, consistent: bool) -> HistoBuilder {
+        self.consistent = consistent;
+        self
+    }
+
+    /// Allocate the configured `Histo`.
+    ///
+    /// Panics if the configuration is invalid. See `try_build`.
+    pub fn build(self) -> Histo {
+        match self.try_build() {
+            Ok(histo) => histo,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Allocate the configured `Histo`, or return an error if any of
+    /// its settings is invalid.
+    pub fn try_build(self) -> Result<Histo, BuildError> {
+        if !(self.precision.is_finite() && self.precision > 0.) {
+            return Err(BuildError::Precision(self.precision));
+        }
+        if !valid_bucket_count(self.max_buckets, self.signed) {
+            return Err(BuildError::Buckets(self.max_buckets));
+        }
+        let (min, max) = self.valid;
+        if min.is_nan() || max.is_nan() || min > max {
+            return Err(BuildError::ValidRange(min, max));
+        }
+        #[cfg(feature = "std")]
+        {
+            if self.top_k == Some(0) {
+                return Err(BuildError::TopK(0));
+            }
+        }
+        #[cfg(feature = "reservoir")]
+        {
+            if self.reservoir == Some(0) {
+                return Err(BuildError::Reservoir(0));
+            }
+        }
+
+        // the number of buckets needed to cover every finite value
+        let needed = math::ceil(self.precision * math::ln(f64::MAX) + 1.);
+        let mut buckets = if needed < self.max_buckets as f64 {
+            needed as usize
+        } else {
+            self.max_buckets
+        };
+        if self.signed {
+            buckets = (buckets * 2).min(self.max_buckets);
+        }
+
+        let mut ret = Histo::new(Scale {
+            precision: self.precision,
+            buckets,
+            signed: self.signed,
+        });
+        ret.valid = self.valid;
+        ret.tracked = self.tracked;
+        if self.consistent {
+            ret.seqlock = Some(SeqLock::default());
+        }
+
+        #[cfg(feature = "std")]
+        {
+            if let Some(k) = self.top_k {
+                ret.top = Some(TopK::new(k));
+            }
+        }
+        #[cfg(feature = "reservoir")]
+        {
+            if let Some(capacity) = self.reservoir {
+                ret.reservoir = Some(Reservoir::new(capacity));
+            }
+        }
+
+        Ok(ret)
+    }
+}
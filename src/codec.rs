@@ -0,0 +1,251 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::Ordering;
+
+use super::{Histo, Scale, Tracked, BUCKETS};
+
+// bumped whenever the encoding changes incompatibly
+const VERSION: u8 = 4;
+
+/// An error returned by `Histo::from_bytes` for input that was not
+/// produced by `Histo::to_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended before the histogram was complete.
+    Truncated,
+    /// The input was encoded by an unsupported version of this crate.
+    Version(u8),
+    /// The encoded bucketing or bucket counts are invalid.
+    Invalid,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "histogram encoding is truncated"),
+            DecodeError::Version(version) => {
+                write!(f, "unsupported histogram encoding version {}", version)
+            }
+            DecodeError::Invalid => write!(f, "histogram encoding is invalid"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+impl Histo {
+    /// Encode this histogram into a compact binary form that can be
+    /// decoded by `from_bytes`. Only nonzero buckets are written, as
+    /// varint-encoded gaps between bucket indices and their counts, so
+    /// the size is proportional to the number of distinct buckets used.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.push(VERSION);
+
+        buf.extend_from_slice(&self.scale.precision.to_bits().to_le_bytes());
+        put_varint(&mut buf, self.scale.buckets as u64);
+        buf.push(
+            self.scale.signed as u8
+                | (self.tracked.log_sum as u8) << 1
+                | (self.tracked.sum_sq as u8) << 2,
+        );
+
+        for total in &[
+            &self.count,
+            &self.sum,
+            &self.log_count,
+            &self.sq_count,
+            &self.overflows,
+            &self.clamped,
+        ] {
+            put_varint(&mut buf, total.load(Ordering::Acquire));
+        }
+        for bits in &[
+            &self.residual,
+            &self.log_sum,
+            &self.sum_sq,
+            &self.min,
+            &self.max,
+        ] {
+            buf.extend_from_slice(&bits.load(Ordering::Acquire).to_le_bytes());
+        }
+
+        let nonzero: Vec<(usize, usize)> = self
+            .vals
+            .iter()
+            .map(|val| val.load(Ordering::Acquire))
+            .enumerate()
+            .filter(|(_, count)| *count != 0)
+            .collect();
+
+        put_varint(&mut buf, nonzero.len() as u64);
+        let mut last = 0;
+        for (idx, count) in nonzero {
+            put_varint(&mut buf, (idx - last) as u64);
+            put_varint(&mut buf, count as u64);
+            last = idx;
+        }
+
+        buf
+    }
+
+    /// Decode a histogram encoded by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Histo, DecodeError> {
+        let mut reader = Reader(bytes);
+
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(DecodeError::Version(version));
+        }
+
+        let precision = f64::from_bits(reader.u64()?);
+        let buckets = reader.varint()?;
+        let flags = reader.byte()?;
+        if flags > 0b111 {
+            return Err(DecodeError::Invalid);
+        }
+        let signed = flags & 1 != 0;
+        let tracked = Tracked {
+            log_sum: flags & 0b10 != 0,
+            sum_sq: flags & 0b100 != 0,
+        };
+        if !(precision.is_finite() && precision > 0.) || buckets == 0 || buckets > BUCKETS as u64 {
+            return Err(DecodeError::Invalid);
+        }
+
+        let mut ret = Histo::new(Scale {
+            precision,
+            buckets: buckets as usize,
+            signed,
+        });
+        ret.tracked = tracked;
+
+        for total in &[
+            &ret.count,
+            &ret.sum,
+            &ret.log_count,
+            &ret.sq_count,
+            &ret.overflows,
+            &ret.clamped,
+        ] {
+            total.store(reader.varint()?, Ordering::Relaxed);
+        }
+        for bits in &[&ret.residual, &ret.log_sum, &ret.sum_sq, &ret.min, &ret.max] {
+            bits.store(reader.u64()?, Ordering::Relaxed);
+        }
+
+        let nonzero = reader.varint()?;
+        let mut idx = 0;
+        for i in 0..nonzero {
+            let delta = reader.varint()?;
+            if i != 0 && delta == 0 {
+                return Err(DecodeError::Invalid);
+            }
+            idx += delta;
+            if idx >= buckets {
+                return Err(DecodeError::Invalid);
+            }
+            let count = reader.varint()?;
+            if count == 0 || count > usize::MAX as u64 {
+                return Err(DecodeError::Invalid);
+            }
+            ret.vals[idx as usize].store(count as usize, Ordering::Relaxed);
+        }
+
+        if !reader.0.is_empty() {
+            return Err(DecodeError::Invalid);
+        }
+
+        Ok(ret)
+    }
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let (first, rest) = self.0.split_first().ok_or(DecodeError::Truncated)?;
+        self.0 = rest;
+        Ok(*first)
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        if self.0.len() < 8 {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(8);
+        self.0 = rest;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(head);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(DecodeError::Invalid);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::Invalid)
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn bytes() {
+    let c = Histo::builder()
+        .track_geometric_mean(true)
+        .track_variance(true)
+        .build();
+    for i in 0..100 {
+        c.measure(i * i * 1000);
+        c.measure(0.5);
+    }
+    c.measure_u64(u64::MAX);
+
+    let bytes = c.to_bytes();
+    let decoded = Histo::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded, c);
+    assert_eq!(decoded.mean(), c.mean());
+    assert_eq!(decoded.geometric_mean(), c.geometric_mean());
+    assert_eq!(decoded.variance(), c.variance());
+    assert_eq!(decoded.min(), c.min());
+    assert_eq!(decoded.max(), c.max());
+    assert_eq!(decoded.clamped_count(), c.clamped_count());
+    for p in 0..=100 {
+        assert_eq!(decoded.percentile(p as f64), c.percentile(p as f64));
+    }
+
+    // a handful of bytes per nonzero bucket, rather than one word for
+    // every bucket or a textual representation of each
+    assert!(bytes.len() < 64 + 101 * 4, "{} bytes", bytes.len());
+
+    let signed = Histo::builder().signed(true).precision(10.).build();
+    signed.measure(-5);
+    assert_eq!(Histo::from_bytes(&signed.to_bytes()).unwrap(), signed);
+
+    assert_eq!(
+        Histo::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(DecodeError::Truncated)
+    );
+    assert_eq!(Histo::from_bytes(&[1]), Err(DecodeError::Version(1)));
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(Histo::from_bytes(&trailing), Err(DecodeError::Invalid));
+}
@@ -0,0 +1,48 @@
+use super::Histo;
+
+/// A `Copy` reference to a `Histo` for measuring from several threads
+/// without wrapping the histogram in an `Arc`, typically used with
+/// `std::thread::scope`.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoHandle<'a> {
+    histo: &'a Histo,
+}
+
+impl<'a> HistoHandle<'a> {
+    pub(crate) fn new(histo: &'a Histo) -> HistoHandle<'a> {
+        HistoHandle { histo }
+    }
+
+    /// Record a value, as with `Histo::measure`.
+    #[inline]
+    pub fn measure<T: Into<f64>>(self, raw_value: T) -> usize {
+        self.histo.measure(raw_value)
+    }
+
+    /// Return the `Histo` that this handle measures into.
+    pub fn histo(self) -> &'a Histo {
+        self.histo
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn handle() {
+    use std::thread;
+
+    let c = Histo::default();
+    let handle = c.handle();
+
+    thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..100 {
+                    handle.measure(t * 100 + i);
+                }
+            });
+        }
+    });
+
+    assert_eq!(c.count(), 400);
+    assert_eq!(handle.histo().max(), 399.);
+}
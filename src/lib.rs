@@ -6,7 +6,10 @@
 //! Uses Relaxed atomics during collection.
 //!
 //! When you create it, it allocates 65k AtomicUsize's
-//! that it uses for incrementing. Generating reports
+//! that it uses for incrementing, or AtomicU32's with the
+//! `small_buckets` feature, or AtomicU16's with the
+//! `u16_buckets` feature, which takes precedence if
+//! both are enabled. Generating reports
 //! after running workloads on dozens of `Histo`'s
 //! does not result in a perceptible delay, but it
 //! might not be acceptable for use in low-latency
@@ -16,215 +19,3750 @@
 //! during collection, while initial allocation and
 //! postprocessing delays are acceptable.
 //!
-//! Future work to further reduce collection latency
-//! may include using thread-local caches that perform
-//! no atomic operations until they are dropped, when
-//! they may atomically aggregate their measurements
-//! into the shared collector that will be used for
-//! reporting.
+//! To further reduce collection latency, `Histo::local`
+//! creates a `LocalHisto` cache that performs no atomic
+//! operations until it is flushed or dropped, when it
+//! atomically aggregates its measurements into the
+//! shared collector that will be used for reporting.
+//!
+//! The `std` feature is enabled by default. Without it the crate
+//! is `no_std` and only requires `alloc`, though `Timer`,
+//! `ShardedHisto`, the `histogram!` registry, `spawn_reporter` and
+//! the `print_percentiles` methods are unavailable.
+//!
+//! With the `saturating` feature, out-of-range arguments such as
+//! percentiles above 100 or negative weights are clamped to the
+//! nearest valid value rather than asserted, and histograms with
+//! different bucketing are combined by re-bucketing their values.
+//!
+//! The `reservoir` feature adds `HistoBuilder::reservoir`, which keeps
+//! a random sample of raw values so that `Histo::exact_percentile` can
+//! validate the bucketed percentiles during development.
 #![deny(missing_docs)]
 #![cfg_attr(test, deny(warnings))]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
 
-use std::fmt::{self, Debug};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::{format, vec, vec::Vec};
+use core::fmt::{self, Debug};
+use core::iter::{FromIterator, Sum};
+use core::ops::{Add, AddAssign};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use bucket::Bucket;
+use seqlock::{SeqLock, WriteGuard};
+
+pub use batch::Batch;
+pub use builder::{BuildError, HistoBuilder};
+pub use codec::DecodeError;
+pub use handle::HistoHandle;
+pub use local::LocalHisto;
+#[cfg(feature = "std")]
+pub use registry::{registered, report_all};
+pub use report::Percentiles;
+#[cfg(feature = "std")]
+pub use reporter::{spawn_reporter, Reporter};
+#[cfg(feature = "std")]
+pub use sharded::ShardedHisto;
+pub use snapshot::Snapshot;
+#[cfg(feature = "std")]
+pub use timer::{Clock, MonotonicClock, Timer};
+pub use windowed::WindowedHisto;
+
+mod batch;
+mod bucket;
+mod builder;
+mod codec;
+mod handle;
+mod local;
+mod math;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+#[cfg(feature = "std")]
+mod registry;
+mod report;
+#[cfg(feature = "std")]
+mod reporter;
+#[cfg(feature = "reservoir")]
+mod reservoir;
+mod seqlock;
+#[cfg(feature = "std")]
+mod sharded;
+mod snapshot;
+#[cfg(feature = "testing")]
+mod testing;
+#[cfg(feature = "std")]
+mod timer;
+#[cfg(feature = "std")]
+mod top;
+mod windowed;
 
 const PRECISION: f64 = 100.;
 const BUCKETS: usize = 1 << 16;
+const PERCENTILES: [f64; 10] = [0., 50., 75., 90., 95., 97.5, 99., 99.9, 99.99, 100.];
 
 /// A histogram collector that uses zero-configuration logarithmic buckets.
+///
+/// `Histo` is `Send` and `Sync` because all of its mutable state is held
+/// in atomics, so it may be shared between threads by reference or
+/// through an `Arc`. With scoped threads no `Arc` is needed, and
+/// `Histo::handle` provides a `Copy` handle to move into each thread:
+///
+/// ```
+/// let histo = historian::Histo::default();
+/// let handle = histo.handle();
+///
+/// std::thread::scope(|s| {
+///     for _ in 0..4 {
+///         s.spawn(move || handle.measure(10));
+///     }
+/// });
+///
+/// # #[cfg(not(feature = "disable"))]
+/// assert_eq!(histo.count(), 4);
+/// ```
 pub struct Histo {
-    vals: Vec<AtomicUsize>,
-    sum: AtomicUsize,
-    count: AtomicUsize,
+    vals: Vec<Bucket>,
+    sum: AtomicU64,
+    // the total difference between each value and its rounded
+    // contribution to `sum`, stored as f64 bits
+    residual: AtomicU64,
+    // the sum of the logarithms of positive values, stored as f64 bits,
+    // and the number of values that contributed to it, if tracked
+    log_sum: AtomicU64,
+    log_count: AtomicU64,
+    // the sum of the squares of finite values, stored as f64 bits, and
+    // the number of values that contributed to it, if tracked
+    sum_sq: AtomicU64,
+    sq_count: AtomicU64,
+    count: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+    overflows: AtomicU64,
+    clamped: AtomicU64,
+    #[cfg(feature = "std")]
+    top: Option<top::TopK>,
+    #[cfg(feature = "reservoir")]
+    reservoir: Option<reservoir::Reservoir>,
+    // the inclusive range of values accepted by `observe_valid`
+    valid: (f64, f64),
+    tracked: Tracked,
+    // present if snapshots must be consistent with concurrent writes
+    seqlock: Option<SeqLock>,
+    scale: Scale,
 }
 
 impl Default for Histo {
     fn default() -> Histo {
-        let mut vals = Vec::with_capacity(BUCKETS);
-        vals.resize_with(BUCKETS, Default::default);
+        Histo::builder().build()
+    }
+}
 
+impl Clone for Histo {
+    /// Copy the current state of this histogram into a new one that is
+    /// unaffected by further measurements.
+    fn clone(&self) -> Histo {
         Histo {
-            vals,
-            sum: AtomicUsize::new(0),
-            count: AtomicUsize::new(0),
+            vals: self
+                .vals
+                .iter()
+                .map(|val| Bucket::new(val.load(Ordering::Acquire)))
+                .collect(),
+            sum: AtomicU64::new(self.sum.load(Ordering::Acquire)),
+            residual: AtomicU64::new(self.residual.load(Ordering::Acquire)),
+            log_sum: AtomicU64::new(self.log_sum.load(Ordering::Acquire)),
+            log_count: AtomicU64::new(self.log_count.load(Ordering::Acquire)),
+            sum_sq: AtomicU64::new(self.sum_sq.load(Ordering::Acquire)),
+            sq_count: AtomicU64::new(self.sq_count.load(Ordering::Acquire)),
+            count: AtomicU64::new(self.count.load(Ordering::Acquire)),
+            min: AtomicU64::new(self.min.load(Ordering::Acquire)),
+            max: AtomicU64::new(self.max.load(Ordering::Acquire)),
+            overflows: AtomicU64::new(self.overflows.load(Ordering::Acquire)),
+            clamped: AtomicU64::new(self.clamped.load(Ordering::Acquire)),
+            #[cfg(feature = "std")]
+            top: self.top.clone(),
+            #[cfg(feature = "reservoir")]
+            reservoir: self.reservoir.clone(),
+            valid: self.valid,
+            tracked: self.tracked,
+            seqlock: self.seqlock.as_ref().map(|_| SeqLock::default()),
+            scale: self.scale,
+        }
+    }
+}
+
+impl PartialEq for Histo {
+    /// Two histograms are equal if they share the same bucketing and
+    /// have recorded the same distribution of values.
+    fn eq(&self, other: &Histo) -> bool {
+        self.scale == other.scale
+            && self.count() == other.count()
+            && self.sum() == other.sum()
+            && self
+                .vals
+                .iter()
+                .zip(other.vals.iter())
+                .all(|(a, b)| a.load(Ordering::Acquire) == b.load(Ordering::Acquire))
+    }
+}
+
+impl AddAssign<f64> for Histo {
+    /// Record a value, as with `measure`.
+    fn add_assign(&mut self, value: f64) {
+        self.measure(value);
+    }
+}
+
+impl AddAssign<u64> for Histo {
+    /// Record a value, as with `measure`.
+    fn add_assign(&mut self, value: u64) {
+        self.measure(value as f64);
+    }
+}
+
+impl AddAssign<f64> for &Histo {
+    /// Record a value into a shared histogram, as with `measure`.
+    fn add_assign(&mut self, value: f64) {
+        self.measure(value);
+    }
+}
+
+impl AddAssign<u64> for &Histo {
+    /// Record a value into a shared histogram, as with `measure`.
+    fn add_assign(&mut self, value: u64) {
+        self.measure(value as f64);
+    }
+}
+
+impl Add for &Histo {
+    type Output = Histo;
+
+    /// Combine two histograms into a new one, as with `merge`.
+    fn add(self, other: &Histo) -> Histo {
+        let ret = self.clone();
+        ret.merge(other);
+        ret
+    }
+}
+
+impl AddAssign<&Histo> for Histo {
+    /// Add all observations from `other`, as with `merge`.
+    fn add_assign(&mut self, other: &Histo) {
+        self.merge(other);
+    }
+}
+
+impl Sum for Histo {
+    /// Merge every histogram into the first one, keeping its bucketing.
+    /// Returns an empty `Histo::default()` if there are none.
+    fn sum<I: Iterator<Item = Histo>>(mut iter: I) -> Histo {
+        let ret = iter.next().unwrap_or_default();
+        for histo in iter {
+            ret.merge(&histo);
+        }
+        ret
+    }
+}
+
+impl<'a> Sum<&'a Histo> for Histo {
+    /// Merge every histogram into a copy of the first one, keeping its
+    /// bucketing. Returns an empty `Histo::default()` if there are none.
+    fn sum<I: Iterator<Item = &'a Histo>>(mut iter: I) -> Histo {
+        let ret = iter.next().cloned().unwrap_or_default();
+        for histo in iter {
+            ret.merge(histo);
+        }
+        ret
+    }
+}
+
+impl Extend<Histo> for Histo {
+    /// Merge every histogram into this one, as with `merge`.
+    fn extend<I: IntoIterator<Item = Histo>>(&mut self, iter: I) {
+        for histo in iter {
+            self.merge(&histo);
+        }
+    }
+}
+
+impl<'a> Extend<&'a Histo> for Histo {
+    /// Merge every histogram into this one, as with `merge`.
+    fn extend<I: IntoIterator<Item = &'a Histo>>(&mut self, iter: I) {
+        for histo in iter {
+            self.merge(histo);
         }
     }
 }
 
-unsafe impl Send for Histo {}
+impl FromIterator<f64> for Histo {
+    /// Build a histogram with the default bucketing by measuring every
+    /// value, which is convenient for test fixtures and offline analysis.
+    fn from_iter<I: IntoIterator<Item = f64>>(iter: I) -> Histo {
+        let ret = Histo::default();
+        for value in iter {
+            ret.measure(value);
+        }
+        ret
+    }
+}
 
 impl Debug for Histo {
-    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
-        const PS: [f64; 10] = [0., 50., 75., 90., 95., 97.5, 99., 99.9, 99.99, 100.];
-        f.write_str("Histogram[")?;
+    fn fmt(&self, f: &mut fmt::Formatter) -> core::result::Result<(), fmt::Error> {
+        f.write_str(&self.format_percentiles(&PERCENTILES))
+    }
+}
+
+impl fmt::Display for Histo {
+    /// Write a table of the count, mean and some common percentiles,
+    /// one per line with the labels right-aligned.
+    fn fmt(&self, f: &mut fmt::Formatter) -> core::result::Result<(), fmt::Error> {
+        write!(f, "{:>6}  {}", "count", self.count())?;
+        write!(f, "\n{:>6}  {:.2}", "mean", self.mean())?;
 
-        for p in &PS {
-            let res = self.percentile(*p).round();
-            let line = format!("({} -> {}) ", p, res);
-            f.write_str(&*line)?;
+        let results = self.quantiles(&PERCENTILES);
+        for (p, res) in PERCENTILES.iter().zip(results) {
+            write!(f, "\n{:>6}  {}", format!("p{}", p), math::round(res))?;
         }
 
-        f.write_str("]")
+        Ok(())
     }
 }
 
 impl Histo {
-    /// Record a value.
+    fn new(scale: Scale) -> Histo {
+        let mut vals = Vec::with_capacity(scale.buckets);
+        vals.resize_with(scale.buckets, Default::default);
+
+        Histo {
+            vals,
+            sum: AtomicU64::new(0),
+            residual: AtomicU64::new(0),
+            log_sum: AtomicU64::new(0),
+            log_count: AtomicU64::new(0),
+            sum_sq: AtomicU64::new(0),
+            sq_count: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+            min: AtomicU64::new(f64::INFINITY.to_bits()),
+            max: AtomicU64::new(f64::NEG_INFINITY.to_bits()),
+            overflows: AtomicU64::new(0),
+            clamped: AtomicU64::new(0),
+            #[cfg(feature = "std")]
+            top: None,
+            #[cfg(feature = "reservoir")]
+            reservoir: None,
+            valid: (f64::NEG_INFINITY, f64::INFINITY),
+            tracked: Tracked::default(),
+            seqlock: None,
+            scale,
+        }
+    }
+
+    /// Create a histogram that preserves the sign of measured values,
+    /// by mapping negative values to a mirrored region of the buckets.
+    /// This halves the range of representable magnitudes. Negative values
+    /// count towards `mean` and `sum_f64`, while `sum` reports a negative
+    /// total as 0 since it is unsigned.
+    pub fn signed() -> Histo {
+        Histo::builder().signed(true).build()
+    }
+
+    /// Create a `HistoBuilder` for trading off memory usage against
+    /// accuracy. The defaults produce the same histogram as
+    /// `Histo::default`.
+    pub fn builder() -> HistoBuilder {
+        HistoBuilder::default()
+    }
+
+    /// Create a default histogram populated from `(bucket, count)` pairs,
+    /// with bucket indices as produced by `compress`. Since the original
+    /// values are unknown, `sum` and the other statistics are computed
+    /// from the value of each bucket.
+    pub fn from_buckets<I: IntoIterator<Item = (u16, usize)>>(buckets: I) -> Histo {
+        let ret = Histo::builder()
+            .track_geometric_mean(true)
+            .track_variance(true)
+            .build();
+
+        for (idx, count) in buckets {
+            if count == 0 || ret.increment(idx as usize, count).is_none() {
+                continue;
+            }
+
+            let rounded = math::round(ret.scale.decompress(idx)) as u64;
+            let n = count as u64;
+            add_sum(&ret.sum, &ret.residual, rounded, n);
+            ret.count.fetch_add(n, Ordering::Relaxed);
+
+            let value = ret.scale.decompress(idx);
+            add_f64(&ret.sum_sq, value * value * n as f64);
+            ret.sq_count.fetch_add(n, Ordering::Relaxed);
+            if value > 0. {
+                add_f64(&ret.log_sum, math::ln(value) * n as f64);
+                ret.log_count.fetch_add(n, Ordering::Relaxed);
+            }
+        }
+
+        ret.approximate_extremes();
+        ret
+    }
+
+    /// Create a default histogram from the raw parts returned by
+    /// `into_parts`: a count for each of the 2^16 default buckets, along
+    /// with `sum` and `count`. As with `from_buckets`, the remaining
+    /// statistics are computed from the value of each bucket.
+    pub fn from_parts(vals: Vec<usize>, sum: u64, count: u64) -> Histo {
+        assert_eq!(
+            vals.len(),
+            BUCKETS,
+            "from_parts requires a count for every default bucket"
+        );
+
+        let ret = Histo::from_buckets(
+            vals.into_iter()
+                .enumerate()
+                .map(|(idx, count)| (idx as u16, count)),
+        );
+        ret.sum.store(sum, Ordering::Relaxed);
+        ret.count.store(count, Ordering::Relaxed);
+        ret
+    }
+
+    /// Deconstruct this histogram into the count of each bucket, `sum`
+    /// and `count`, which `from_parts` can reassemble for histograms
+    /// with the default bucketing.
+    pub fn into_parts(self) -> (Vec<usize>, u64, u64) {
+        let sum = self.sum();
+        let count = self.count();
+        let vals = self
+            .vals
+            .into_iter()
+            .map(|val| val.load(Ordering::Acquire))
+            .collect();
+        (vals, sum, count)
+    }
+
+    /// Record a value. NAN is ignored, and values beyond the range of the
+    /// buckets are recorded in the outermost bucket and counted by
+    /// `clamped_count`. Infinite values do not contribute to `sum`. If the
+    /// value's bucket is already full, the value is dropped and counted
+    /// by `overflow_count` instead.
+    ///
+    /// Returns the count of the value's bucket after recording it, which
+    /// is 0 for NAN and `usize::MAX` if the value overflowed its bucket.
+    /// Use `observe` when the count is not needed.
     #[inline]
     pub fn measure<T: Into<f64>>(&self, raw_value: T) -> usize {
+        self.measure_n(raw_value, 1)
+    }
+
+    /// Record a value in the same way as `measure`, without returning
+    /// the resulting bucket count.
+    #[inline]
+    pub fn observe<T: Into<f64>>(&self, raw_value: T) {
+        self.measure_n(raw_value, 1);
+    }
+
+    /// Record a value in the same way as `observe`, unless it is outside
+    /// of the range configured by `HistoBuilder::valid_range`, such as a
+    /// sentinel value standing in for a missing measurement. Without a
+    /// configured range only NAN is ignored, as with `observe`.
+    #[inline]
+    pub fn observe_valid<T: Into<f64>>(&self, raw_value: T) {
+        let value: f64 = raw_value.into();
+        if self.valid.0 <= value && value <= self.valid.1 {
+            self.measure_n(value, 1);
+        }
+    }
+
+    /// Record `n` observations of the same value at once, in the same
+    /// way as calling `measure` `n` times.
+    #[inline]
+    pub fn measure_n<T: Into<f64>>(&self, raw_value: T, n: usize) -> usize {
         #[cfg(not(feature = "disable"))]
         {
             let value_float: f64 = raw_value.into();
-            self.sum
-                .fetch_add(value_float.round() as usize, Ordering::Relaxed);
+            if value_float.is_nan() {
+                return 0;
+            }
+
+            if !value_float.is_finite() {
+                return self.record(value_float, None, 0., n);
+            }
+
+            let rounded = math::round(value_float) as u64;
+            let residual = value_float - rounded as f64;
 
-            self.count.fetch_add(1, Ordering::Relaxed);
+            self.record(value_float, Some(rounded), residual, n)
+        }
 
-            // compress the value to one of 2**16 values
-            // using logarithmic bucketing
-            let compressed: u16 = compress(value_float);
+        #[cfg(feature = "disable")]
+        {
+            let _ = (raw_value, n);
+            0
+        }
+    }
 
-            // increment the counter for this compressed value
-            self.vals[compressed as usize].fetch_add(1, Ordering::Relaxed) + 1
+    /// Record an integer value. Unlike `measure`, which converts its
+    /// input to an `f64`, this keeps `sum` exact for values above 2^53.
+    #[inline]
+    pub fn measure_u64(&self, value: u64) -> usize {
+        #[cfg(not(feature = "disable"))]
+        {
+            self.record(value as f64, Some(value), 0., 1)
         }
 
         #[cfg(feature = "disable")]
         {
+            let _ = value;
             0
         }
     }
 
+    // Records `n` observations of a non-NAN value, along with its integer
+    // representation for `sum` if it is finite, and the residual by which
+    // that representation differs from the value.
+    #[cfg(not(feature = "disable"))]
+    #[inline]
+    fn record(&self, value_float: f64, rounded: Option<u64>, residual: f64, n: usize) -> usize {
+        // compress the value to one of 2**16 values
+        // using logarithmic bucketing
+        let compressed: u16 = match self.scale.try_compress(value_float) {
+            Some(compressed) => compressed,
+            None => {
+                self.clamped.fetch_add(n as u64, Ordering::Relaxed);
+                self.scale.saturate(value_float)
+            }
+        };
+
+        if n == 0 {
+            return self.vals[compressed as usize].load(Ordering::Relaxed);
+        }
+
+        let _guard = self.write_guard();
+
+        // increment the counter for this compressed value
+        let new = match self.increment(compressed as usize, n) {
+            Some(new) => new,
+            None => return usize::MAX,
+        };
+
+        if let Some(rounded) = rounded {
+            add_sum(&self.sum, &self.residual, rounded, n as u64);
+            if self.tracked.sum_sq {
+                add_f64(&self.sum_sq, value_float * value_float * n as f64);
+                self.sq_count.fetch_add(n as u64, Ordering::Relaxed);
+            }
+        }
+        if residual != 0. {
+            add_f64(&self.residual, residual * n as f64);
+        }
+        if self.tracked.log_sum && value_float > 0. && value_float.is_finite() {
+            add_f64(&self.log_sum, math::ln(value_float) * n as f64);
+            self.log_count.fetch_add(n as u64, Ordering::Relaxed);
+        }
+
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+
+        #[cfg(feature = "std")]
+        {
+            if let Some(top) = &self.top {
+                top.insert(value_float, n);
+            }
+        }
+        #[cfg(feature = "reservoir")]
+        {
+            if let Some(reservoir) = &self.reservoir {
+                reservoir.insert(value_float, n);
+            }
+        }
+
+        update_f64(&self.min, value_float, |new, min| new < min);
+        update_f64(&self.max, value_float, |new, max| new > max);
+
+        new
+    }
+
+    // Marks the start of a write that snapshots must not observe
+    // halfway, if this histogram was built with `consistent_snapshots`.
+    #[inline]
+    fn write_guard(&self) -> Option<WriteGuard<'_>> {
+        self.seqlock.as_ref().map(SeqLock::write)
+    }
+
+    // Adds `n` observations to the bucket at `idx`, returning its new
+    // count, or `None` if that would overflow the bucket. Overflowing
+    // observations are dropped and tallied in `overflows` instead.
+    #[inline]
+    fn increment(&self, idx: usize, n: usize) -> Option<usize> {
+        let new = self.vals[idx].add(n);
+        if new.is_none() {
+            self.overflows.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        new
+    }
+
+    /// Return the number of observations that were dropped because
+    /// their bucket was already at its maximum count.
+    pub fn overflow_count(&self) -> u64 {
+        self.overflows.load(Ordering::Acquire)
+    }
+
+    /// Return the number of observations that were beyond the range of
+    /// the buckets, including infinities, and were recorded in the
+    /// outermost bucket instead.
+    pub fn clamped_count(&self) -> u64 {
+        self.clamped.load(Ordering::Acquire)
+    }
+
+    /// Record a `Duration` in nanoseconds.
+    #[inline]
+    pub fn measure_duration(&self, duration: Duration) -> usize {
+        self.measure(duration.as_nanos() as f64)
+    }
+
+    /// Record an elapsed time in nanoseconds, as measured by a `Timer`.
+    /// Passing synthetic durations here makes timing tests deterministic.
+    #[inline]
+    pub fn measure_elapsed(&self, nanos: u64) -> usize {
+        self.measure_u64(nanos)
+    }
+
+    /// Start a `Timer` that records the time elapsed until it is dropped
+    /// using `measure_elapsed`.
+    #[cfg(feature = "std")]
+    pub fn timer(&self) -> Timer<'_> {
+        Timer::new(self, MonotonicClock)
+    }
+
+    /// Start a `Timer` that reads the time from `clock` rather than from
+    /// `Instant`, such as a synthetic clock in tests.
+    #[cfg(feature = "std")]
+    pub fn timer_with<C: Clock>(&self, clock: C) -> Timer<'_, C> {
+        Timer::new(self, clock)
+    }
+
+    /// Run `f` and record how long it took using `measure_duration`,
+    /// returning its result. Nothing is recorded if `f` panics, unlike
+    /// with a `Timer`, which records the time as the panic unwinds.
+    #[cfg(feature = "std")]
+    pub fn measure_timed<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        let start = std::time::Instant::now();
+        let ret = f();
+        self.measure_duration(start.elapsed());
+        ret
+    }
+
+    /// Retrieve a percentile [0-100] of durations recorded by
+    /// `measure_duration`. Returns a zero `Duration` if no metrics
+    /// have been collected yet.
+    pub fn percentile_duration(&self, p: f64) -> Duration {
+        let nanos = self.percentile(p);
+        if nanos.is_nan() {
+            return Duration::default();
+        }
+
+        Duration::from_nanos(math::round(nanos) as u64)
+    }
+
     /// Retrieve a percentile [0-100]. Returns NAN if no metrics have been
-    /// collected yet.
+    /// collected yet. Percentiles outside of [0-100] are clamped to the
+    /// nearest valid one, after failing a debug assertion.
     pub fn percentile(&self, p: f64) -> f64 {
+        let p = clamp_percentile(p);
+
+        self.try_percentile(p).unwrap_or(f64::NAN)
+    }
+
+    /// Retrieve a percentile [0-100] as an `f32`, for compact reporting.
+    /// Computation is still performed in `f64`.
+    pub fn percentile_f32(&self, p: f64) -> f32 {
+        self.percentile(p) as f32
+    }
+
+    /// Retrieve the 50th percentile. Returns NAN if no metrics have been
+    /// collected yet.
+    pub fn median(&self) -> f64 {
+        self.percentile(50.)
+    }
+
+    /// Retrieve a percentile [0-100]. Returns `None` if no metrics have
+    /// been collected yet, or if `p` is not within [0-100].
+    pub fn try_percentile(&self, p: f64) -> Option<f64> {
+        self.percentile_with(p, Ordering::Acquire)
+    }
+
+    /// Retrieve a percentile [0-100] using `Relaxed` loads, which avoids
+    /// the cost of synchronizing with concurrent measurements. This is
+    /// only guaranteed to observe measurements that happened-before this
+    /// call through some external synchronization, such as joining the
+    /// threads that measured them. Returns NAN if no metrics have been
+    /// collected yet.
+    pub fn percentile_relaxed(&self, p: f64) -> f64 {
+        let p = clamp_percentile(p);
+
+        self.percentile_with(p, Ordering::Relaxed)
+            .unwrap_or(f64::NAN)
+    }
+
+    fn percentile_with(&self, p: f64, order: Ordering) -> Option<f64> {
         #[cfg(not(feature = "disable"))]
         {
-            assert!(p <= 100., "percentiles must not exceed 100.0");
+            if !(0. ..=100.).contains(&p) {
+                return None;
+            }
 
-            let count = self.count.load(Ordering::Acquire);
+            let count = self.count.load(order);
 
             if count == 0 {
-                return std::f64::NAN;
+                return None;
             }
 
-            let mut target = count as f64 * (p / 100.);
-            if target == 0. {
-                target = 1.;
+            if p == 100. {
+                return Some(self.highest_bucket(order));
             }
 
-            let mut sum = 0.;
+            let target = percentile_target(count, p);
+
+            let mut sum = 0;
 
             for (idx, val) in self.vals.iter().enumerate() {
-                let count = val.load(Ordering::Acquire);
-                sum += count as f64;
+                let count = val.load(order);
+                sum += count as u64;
 
                 if sum >= target {
-                    return decompress(idx as u16);
+                    return Some(self.scale.decompress(idx as u16));
                 }
             }
         }
 
-        std::f64::NAN
-    }
+        #[cfg(feature = "disable")]
+        let _ = (p, order);
 
-    /// Dump out some common percentiles.
-    pub fn print_percentiles(&self) {
-        println!("{:?}", self);
+        None
     }
 
-    /// Return the sum of all observations in this histogram.
-    pub fn sum(&self) -> usize {
-        self.sum.load(Ordering::Acquire)
-    }
+    /// Retrieve a percentile [0-100], linearly interpolated between the
+    /// bounds of the bucket that contains it according to how far into
+    /// that bucket's observations the percentile falls. This avoids the
+    /// steps between buckets that `percentile` produces on sparse data.
+    /// Returns NAN if no metrics have been collected yet.
+    pub fn percentile_interpolated(&self, p: f64) -> f64 {
+        let p = clamp_percentile(p);
 
-    /// Return the count of observations in this histogram.
-    pub fn count(&self) -> usize {
-        self.count.load(Ordering::Acquire)
-    }
-}
+        let count = self.count();
 
-// compress takes a value and lossily shrinks it to an u16 to facilitate
-// bucketing of histogram values, staying roughly within 1% of the true
-// value. This fails for large values of 1e142 and above, and is
-// inaccurate for values closer to 0 than +/- 0.51 or +/- math.Inf.
-#[inline]
-fn compress<T: Into<f64>>(value: T) -> u16 {
-    let value: f64 = value.into();
-    let abs = value.abs();
-    let boosted = 1. + abs;
-    let ln = boosted.ln();
-    let compressed = PRECISION * ln + 0.5;
-    assert!(compressed <= std::u16::MAX as f64);
-    compressed as u16
-}
-
-// decompress takes a lossily shrunken u16 and returns an f64 within 1% of
-// the original passed to compress.
-#[inline]
-fn decompress(compressed: u16) -> f64 {
-    let unboosted = compressed as f64 / PRECISION;
-    (unboosted.exp() - 1.)
-}
+        if count == 0 {
+            return f64::NAN;
+        }
 
-#[test]
-fn it_works() {
-    let c = Histo::default();
-    assert_eq!(c.measure(2), 1);
-    assert_eq!(c.measure(2), 2);
-    assert_eq!(c.measure(3), 1);
-    assert_eq!(c.measure(3), 2);
-    assert_eq!(c.measure(4), 1);
-    assert_eq!(c.percentile(0.).round() as usize, 2);
-    assert_eq!(c.percentile(40.).round() as usize, 2);
-    assert_eq!(c.percentile(40.1).round() as usize, 3);
-    assert_eq!(c.percentile(80.).round() as usize, 3);
-    assert_eq!(c.percentile(80.1).round() as usize, 4);
-    assert_eq!(c.percentile(100.).round() as usize, 4);
-    c.print_percentiles();
-}
+        let target = percentile_target(count, p);
 
-#[test]
-fn high_percentiles() {
-    let c = Histo::default();
-    for _ in 0..9000 {
-        c.measure(10);
-    }
-    for _ in 0..900 {
-        c.measure(25);
-    }
-    for _ in 0..90 {
-        c.measure(33);
+        let mut sum = 0;
+
+        for (idx, val) in self.vals.iter().enumerate() {
+            let count = val.load(Ordering::Acquire) as u64;
+            if count == 0 {
+                continue;
+            }
+
+            if sum + count >= target {
+                let fraction = ((target - sum) as f64 / count as f64).clamp(0., 1.);
+                let (low, high) = self.scale.bounds(idx as u16);
+                return low + fraction * (high - low);
+            }
+
+            sum += count;
+        }
+
+        f64::NAN
     }
-    for _ in 0..9 {
-        c.measure(47);
+
+    /// Retrieve a percentile [0-100] along with the half-width of the
+    /// bucket it was read from, which bounds the quantization error of
+    /// the result. Returns NAN for both if no metrics have been
+    /// collected yet.
+    pub fn percentile_with_error(&self, p: f64) -> (f64, f64) {
+        let value = self.percentile(p);
+        if value.is_nan() {
+            return (f64::NAN, f64::NAN);
+        }
+
+        let (low, high) = self.scale.bounds(self.scale.compress(value));
+        (value, (high - low) / 2.)
     }
-    c.measure(500);
-    assert_eq!(c.percentile(0.).round() as usize, 10);
-    assert_eq!(c.percentile(99.).round() as usize, 25);
-    assert_eq!(c.percentile(99.89).round() as usize, 33);
-    assert_eq!(c.percentile(99.91).round() as usize, 47);
-    assert_eq!(c.percentile(99.99).round() as usize, 47);
-    assert_eq!(c.percentile(100.).round() as usize, 502);
-}
 
-#[test]
-fn multithreaded() {
-    use std::sync::Arc;
-    use std::thread;
+    /// Return the largest half-width of any populated bucket relative to
+    /// the value it represents, which bounds the relative error of every
+    /// percentile of the data collected so far. Buckets that represent
+    /// zero are skipped, since relative error is undefined there.
+    /// Returns NAN if no such bucket has been populated.
+    pub fn max_relative_error(&self) -> f64 {
+        let mut ret = f64::NAN;
 
-    let h = Arc::new(Histo::default());
-    let mut threads = vec![];
+        for (idx, val) in self.vals.iter().enumerate() {
+            if val.load(Ordering::Acquire) == 0 {
+                continue;
+            }
 
-    for _ in 0..10 {
-        let h = h.clone();
-        threads.push(thread::spawn(move || {
-            h.measure(20);
-        }));
+            let value = self.scale.decompress(idx as u16);
+            if value == 0. {
+                continue;
+            }
+            let (low, high) = self.scale.bounds(idx as u16);
+            let error = (high - low) / 2. / value.abs();
+            if ret.is_nan() || error > ret {
+                ret = error;
+            }
+        }
+
+        ret
     }
 
-    for t in threads.into_iter() {
-        t.join().unwrap();
+    /// Retrieve a percentile [0-100] as the arithmetic midpoint of the
+    /// bounds of the bucket containing it. `percentile` returns the
+    /// logarithmic center of the bucket instead, which lies slightly
+    /// below the midpoint. The midpoint has less bias for data that is
+    /// spread evenly within each bucket, which matters most with coarse
+    /// buckets, while the logarithmic center is what `compress` rounds
+    /// values to. Returns NAN if no metrics have been collected yet.
+    pub fn percentile_midpoint(&self, p: f64) -> f64 {
+        let value = self.percentile(p);
+        if value.is_nan() {
+            return f64::NAN;
+        }
+
+        let (low, high) = self.scale.bounds(self.scale.compress(value));
+        (low + high) / 2.
     }
 
-    assert_eq!(h.percentile(50.).round() as usize, 20);
+    /// Retrieve a percentile [0-100] as the upper bound of the bucket
+    /// containing it, which is never below any observation in that
+    /// bucket. This is the conservative choice when checking that a
+    /// latency percentile stays below a threshold. Returns NAN if no
+    /// metrics have been collected yet.
+    pub fn percentile_ceil(&self, p: f64) -> f64 {
+        let value = self.percentile(p);
+        if value.is_nan() {
+            return f64::NAN;
+        }
+
+        self.scale.bounds(self.scale.compress(value)).1
+    }
+
+    /// Retrieve several percentiles [0-100] using a single scan over the
+    /// buckets, returned in the order requested. Each result is identical
+    /// to calling `percentile` for that percentile.
+    pub fn quantiles(&self, ps: &[f64]) -> Vec<f64> {
+        #[cfg_attr(feature = "disable", allow(unused_mut))]
+        let mut ret = vec![f64::NAN; ps.len()];
+
+        #[cfg(not(feature = "disable"))]
+        {
+            let ps: Vec<f64> = ps.iter().map(|p| clamp_percentile(*p)).collect();
+
+            let count = self.count.load(Ordering::Acquire);
+
+            if count == 0 {
+                return ret;
+            }
+
+            if ps.contains(&100.) {
+                let highest = self.highest_bucket(Ordering::Acquire);
+                for (p, res) in ps.iter().zip(ret.iter_mut()) {
+                    if *p == 100. {
+                        *res = highest;
+                    }
+                }
+            }
+
+            let mut order: Vec<usize> = (0..ps.len())
+                .filter(|i| ps[*i] != 100. && !ps[*i].is_nan())
+                .collect();
+            order.sort_by(|a, b| ps[*a].partial_cmp(&ps[*b]).unwrap());
+            let mut pending = order.into_iter().peekable();
+
+            let mut sum = 0;
+
+            for (idx, val) in self.vals.iter().enumerate() {
+                let count_here = val.load(Ordering::Acquire);
+                sum += count_here as u64;
+
+                while let Some(&i) = pending.peek() {
+                    if sum < percentile_target(count, ps[i]) {
+                        break;
+                    }
+                    ret[i] = self.scale.decompress(idx as u16);
+                    pending.next();
+                }
+
+                if pending.peek().is_none() {
+                    break;
+                }
+            }
+        }
+
+        ret
+    }
+
+    // Returns the value of the highest nonzero bucket, which is always
+    // reported for the 100th percentile so that it can't fall short of
+    // the maximum when racing with concurrent measurements.
+    #[cfg(not(feature = "disable"))]
+    fn highest_bucket(&self, order: Ordering) -> f64 {
+        self.vals
+            .iter()
+            .rposition(|val| val.load(order) != 0)
+            .map_or(f64::NAN, |idx| self.scale.decompress(idx as u16))
+    }
+
+    /// Retrieve the percentile [0-100] of observations at or below the
+    /// bucket containing `value`, which is approximately the inverse of
+    /// `percentile`. Returns NAN if no metrics have been collected yet.
+    pub fn rank(&self, value: f64) -> f64 {
+        let count = self.count();
+
+        if count == 0 || value.is_nan() {
+            return f64::NAN;
+        }
+
+        let compressed = self.scale.compress(value) as usize;
+        let cumulative: usize = self.vals[..=compressed]
+            .iter()
+            .map(|val| val.load(Ordering::Acquire))
+            .sum();
+
+        100. * cumulative as f64 / count as f64
+    }
+
+    /// Retrieve the value of the bucket holding the observation at the
+    /// zero-based `rank` in ascending order, such as `value_at_rank(999)`
+    /// for the 1000th smallest observation. Returns NAN if there are not
+    /// more than `rank` observations.
+    pub fn value_at_rank(&self, rank: usize) -> f64 {
+        let mut seen = 0;
+
+        for (idx, val) in self.vals.iter().enumerate() {
+            seen += val.load(Ordering::Acquire);
+
+            if seen > rank {
+                return self.scale.decompress(idx as u16);
+            }
+        }
+
+        f64::NAN
+    }
+
+    /// Return the number of observations at or below the bucket that
+    /// contains percentile `p` [0-100], such as how many requests were
+    /// no slower than the p90. Returns 0 if no metrics have been
+    /// collected yet.
+    pub fn percentile_count(&self, p: f64) -> usize {
+        let p = clamp_percentile(p);
+
+        let count = self.count();
+
+        if count == 0 {
+            return 0;
+        }
+
+        let target = percentile_target(count, p);
+
+        let mut sum = 0;
+
+        for val in &self.vals {
+            sum += val.load(Ordering::Acquire);
+
+            if sum as u64 >= target {
+                break;
+            }
+        }
+
+        sum
+    }
+
+    /// Capture the nonzero buckets of this histogram into a `Snapshot`
+    /// that answers percentile queries without rescanning every bucket.
+    /// Concurrent measurements may be partially reflected in it unless
+    /// this histogram was built with `HistoBuilder::consistent_snapshots`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self)
+    }
+
+    /// Retrieve the `low` and `high` percentiles [0-100] of a band, such
+    /// as p25-p75, using a single scan over the buckets. Returns NAN for
+    /// both if no metrics have been collected yet. Percentiles outside of
+    /// [0-100] are clamped, and a `high` below `low` is raised to it,
+    /// after failing a debug assertion.
+    pub fn percentile_range(&self, low: f64, high: f64) -> (f64, f64) {
+        let (low, high) = clamp_band(low, high);
+
+        let results = self.quantiles(&[low, high]);
+        (results[0], results[1])
+    }
+
+    /// Take every observation out of this histogram into a `Snapshot`,
+    /// leaving it empty for the next reporting interval. Each bucket is
+    /// atomically swapped with zero, so concurrent measurements land in
+    /// either the snapshot or the next interval and are never lost. With
+    /// `HistoBuilder::consistent_snapshots`, a concurrent `snapshot`
+    /// observes the histogram either before or after the drain.
+    pub fn drain(&self) -> Snapshot {
+        Snapshot::drain(self)
+    }
+
+    /// Iterate over `(value, count)` pairs for every bucket that has
+    /// been measured into, in ascending value order. The value is the
+    /// decompressed representative of the bucket.
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, usize)> + '_ {
+        self.vals.iter().enumerate().filter_map(move |(idx, val)| {
+            let count = val.load(Ordering::Acquire);
+            if count == 0 {
+                None
+            } else {
+                Some((self.scale.decompress(idx as u16), count))
+            }
+        })
+    }
+
+    /// Call `f` with the `(value, count)` of every bucket that has been
+    /// measured into, in ascending value order, as with `buckets`. This
+    /// is convenient for streaming the buckets into a custom export.
+    pub fn for_each_bucket<F: FnMut(f64, usize)>(&self, mut f: F) {
+        for (idx, val) in self.vals.iter().enumerate() {
+            let count = val.load(Ordering::Acquire);
+            if count != 0 {
+                f(self.scale.decompress(idx as u16), count);
+            }
+        }
+    }
+
+    /// Return the number of observations in bucket `idx`, as produced by
+    /// `compress` for the default bucketing. Returns 0 for an index past
+    /// the last bucket of this histogram.
+    pub fn bucket_count(&self, idx: u16) -> usize {
+        self.vals
+            .get(idx as usize)
+            .map_or(0, |val| val.load(Ordering::Acquire))
+    }
+
+    /// Returns the lowest and highest values that are measured into
+    /// bucket `idx`. Because measurements are rounded to the nearest
+    /// bucket, the representative value reported by `buckets` lies
+    /// between these bounds, and the upper bound of each bucket is the
+    /// lower bound of the next.
+    pub fn bucket_bounds(&self, idx: u16) -> (f64, f64) {
+        self.scale.bounds(idx)
+    }
+
+    /// Iterate over `(low, high, count)` triples for every bucket that
+    /// has been measured into, in ascending value order, where `low`
+    /// and `high` are the bounds returned by `bucket_bounds`.
+    pub fn bucket_ranges(&self) -> impl Iterator<Item = (f64, f64, usize)> + '_ {
+        self.vals.iter().enumerate().filter_map(move |(idx, val)| {
+            let count = val.load(Ordering::Acquire);
+            if count == 0 {
+                None
+            } else {
+                let (low, high) = self.scale.bounds(idx as u16);
+                Some((low, high, count))
+            }
+        })
+    }
+
+    /// Collect the `(low, high, count)` of every bucket that has been
+    /// measured into, in ascending value order, as produced by
+    /// `bucket_ranges`. This is suitable for drawing as the bars of a
+    /// histogram with a plotting library.
+    pub fn histogram_data(&self) -> Vec<(f64, f64, u64)> {
+        self.bucket_ranges()
+            .map(|(low, high, count)| (low, high, count as u64))
+            .collect()
+    }
+
+    /// Iterate over the empirical cumulative distribution as
+    /// `(value, fraction)` pairs for every nonzero bucket, in ascending
+    /// value order, where `fraction` is the proportion of observations
+    /// at or below that bucket. Once measurements have quiesced, the
+    /// final fraction is 1.0.
+    pub fn cdf(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let count = self.count() as f64;
+        let mut cumulative = 0;
+
+        self.buckets().map(move |(value, n)| {
+            cumulative += n;
+            (value, cumulative as f64 / count)
+        })
+    }
+
+    /// Dump out some common percentiles to stdout.
+    #[cfg(feature = "std")]
+    pub fn print_percentiles(&self) {
+        self.write_percentiles(&mut std::io::stdout().lock())
+            .expect("failed printing to stdout");
+    }
+
+    /// Write the line printed by `print_percentiles` to `w`, such as a
+    /// log file or an in-memory buffer.
+    #[cfg(feature = "std")]
+    pub fn write_percentiles<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+        writeln!(w, "{}", self.format_percentiles(&PERCENTILES))
+    }
+
+    /// Dump out the given percentiles [0-100].
+    #[cfg(feature = "std")]
+    pub fn print_percentiles_custom(&self, ps: &[f64]) {
+        println!("{}", self.format_percentiles(ps));
+    }
+
+    fn format_percentiles(&self, ps: &[f64]) -> String {
+        let mut ret = String::from("Histogram[");
+
+        let results = self.quantiles(ps);
+        for (p, res) in ps.iter().zip(results) {
+            let line = format!("({} -> {}) ", p, math::round(res));
+            ret.push_str(&line);
+        }
+
+        ret.push(']');
+        ret
+    }
+
+    /// Format a comparison of some common percentiles of this histogram
+    /// against those of `baseline`, one per line, such as
+    /// `p99: 120 -> 95 (-21%)`. The change is omitted when the baseline
+    /// value is zero or missing.
+    pub fn diff_report(&self, baseline: &Histo) -> String {
+        let before = baseline.quantiles(&PERCENTILES);
+        let after = self.quantiles(&PERCENTILES);
+
+        let mut ret = String::new();
+        for ((p, old), new) in PERCENTILES.iter().zip(before).zip(after) {
+            let line = if old != 0. && old.is_finite() && new.is_finite() {
+                let change = (new - old) / old * 100.;
+                format!(
+                    "p{}: {} -> {} ({:+.0}%)\n",
+                    p,
+                    math::round(old),
+                    math::round(new),
+                    change
+                )
+            } else {
+                format!("p{}: {} -> {}\n", p, math::round(old), math::round(new))
+            };
+            ret.push_str(&line);
+        }
+        ret
+    }
+
+    /// Return the sum of all observations in this histogram, rounded to
+    /// the nearest integer. Fractional values accumulate before rounding,
+    /// so many small values still contribute to the total, while integer
+    /// values are summed exactly. Negative values are included, but a
+    /// total below zero is reported as 0 and one beyond `u64::MAX` as
+    /// `u64::MAX`; `sum_f64` returns the total without these limits.
+    pub fn sum(&self) -> u64 {
+        rounded_sum(
+            self.sum.load(Ordering::Acquire),
+            f64::from_bits(self.residual.load(Ordering::Acquire)),
+        )
+    }
+
+    /// Return the sum of all observations in this histogram as an `f64`,
+    /// without rounding, so that it may be negative for a `signed`
+    /// histogram. This is the total that `mean` divides by the count.
+    pub fn sum_f64(&self) -> f64 {
+        self.sum.load(Ordering::Acquire) as f64
+            + f64::from_bits(self.residual.load(Ordering::Acquire))
+    }
+
+    /// Return the count of observations in this histogram.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Create a cheap `Copy` handle for measuring into this histogram,
+    /// which can be moved into scoped threads without an `Arc`.
+    pub fn handle(&self) -> HistoHandle<'_> {
+        HistoHandle::new(self)
+    }
+
+    /// Create a `LocalHisto` that buffers measurements without
+    /// performing any atomic operations, and adds them to this
+    /// histogram when flushed or dropped. Measurements are not
+    /// visible here until then.
+    pub fn local(&self) -> LocalHisto<'_> {
+        LocalHisto::new(self)
+    }
+
+    /// Add all observations from `other` into this histogram.
+    /// This may be called while other threads are still measuring
+    /// into either histogram, in which case the merged result is
+    /// only a fuzzy snapshot of `other`.
+    ///
+    /// Panics if the histograms were built with different bucketing,
+    /// since the same bucket index would represent different values,
+    /// unless the `saturating` feature is enabled.
+    pub fn merge(&self, other: &Histo) {
+        let other = &*self.compatible(other);
+        let _guard = self.write_guard();
+
+        let dropped = self.merge_buckets(other, 0..other.vals.len());
+        self.merge_totals(other, dropped);
+    }
+
+    /// Merge many histograms into a new one, splitting the buckets into
+    /// ranges that are merged on separate threads when there are enough
+    /// histograms to make that worthwhile. The result is the same as
+    /// merging each of them in turn into an empty histogram with their
+    /// bucketing.
+    ///
+    /// Panics if `histos` is empty or if their bucketing differs.
+    #[cfg(feature = "std")]
+    pub fn merge_all(histos: &[Histo]) -> Histo {
+        // below this, spawning threads costs more than it saves
+        const PARALLEL_THRESHOLD: usize = 8;
+
+        let threads = if histos.len() < PARALLEL_THRESHOLD {
+            1
+        } else {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        };
+
+        Histo::merge_all_on(histos, threads)
+    }
+
+    // Implements `merge_all` using the given number of threads.
+    #[cfg(feature = "std")]
+    fn merge_all_on(histos: &[Histo], threads: usize) -> Histo {
+        let first = histos.first().expect("merge_all requires a histogram");
+        let mut ret = Histo::new(first.scale);
+        ret.top = first.top.as_ref().map(top::TopK::empty);
+        ret.valid = first.valid;
+        ret.tracked = first.tracked;
+        ret.seqlock = first.seqlock.as_ref().map(|_| SeqLock::default());
+        let histos: Vec<Cow<'_, Histo>> =
+            histos.iter().map(|histo| ret.compatible(histo)).collect();
+        let histos = &histos[..];
+
+        let buckets = ret.vals.len();
+
+        let dropped: Vec<u64> = if threads <= 1 {
+            histos
+                .iter()
+                .map(|histo| ret.merge_buckets(histo, 0..buckets))
+                .collect()
+        } else {
+            let chunk = buckets.div_ceil(threads);
+            std::thread::scope(|s| {
+                let workers: Vec<_> = (0..buckets)
+                    .step_by(chunk)
+                    .map(|start| {
+                        let range = start..(start + chunk).min(buckets);
+                        let ret = &ret;
+                        s.spawn(move || {
+                            histos
+                                .iter()
+                                .map(|histo| ret.merge_buckets(histo, range.clone()))
+                                .collect::<Vec<u64>>()
+                        })
+                    })
+                    .collect();
+
+                let mut dropped = vec![0; histos.len()];
+                for worker in workers {
+                    for (total, d) in dropped.iter_mut().zip(worker.join().unwrap()) {
+                        *total += d;
+                    }
+                }
+                dropped
+            })
+        };
+
+        for (histo, dropped) in histos.iter().zip(dropped) {
+            ret.merge_totals(histo, dropped);
+        }
+
+        ret
+    }
+
+    // Adds the counts of the buckets of `other` within `range` to this
+    // histogram, returning the number of observations that were dropped
+    // because their bucket overflowed.
+    fn merge_buckets(&self, other: &Histo, range: core::ops::Range<usize>) -> u64 {
+        let mut dropped = 0;
+        for (idx, src) in other.vals[range.clone()].iter().enumerate() {
+            let count = src.load(Ordering::Relaxed);
+            if count != 0 && self.increment(range.start + idx, count).is_none() {
+                dropped += count as u64;
+            }
+        }
+        dropped
+    }
+
+    // Adds the totals and extremes of `other` to this histogram, after
+    // its buckets have been added by `merge_buckets`.
+    fn merge_totals(&self, other: &Histo, dropped: u64) {
+        add_sum(&self.sum, &self.residual, other.sum.load(Ordering::Relaxed), 1);
+        add_f64(&self.residual, f64::from_bits(other.residual.load(Ordering::Relaxed)));
+        add_f64(&self.log_sum, f64::from_bits(other.log_sum.load(Ordering::Relaxed)));
+        self.log_count
+            .fetch_add(other.log_count.load(Ordering::Relaxed), Ordering::Relaxed);
+        add_f64(&self.sum_sq, f64::from_bits(other.sum_sq.load(Ordering::Relaxed)));
+        self.sq_count
+            .fetch_add(other.sq_count.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.count.fetch_add(
+            other.count.load(Ordering::Relaxed).saturating_sub(dropped),
+            Ordering::Relaxed,
+        );
+        self.overflows
+            .fetch_add(other.overflows.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.clamped
+            .fetch_add(other.clamped.load(Ordering::Relaxed), Ordering::Relaxed);
+
+        let other_min = f64::from_bits(other.min.load(Ordering::Relaxed));
+        let other_max = f64::from_bits(other.max.load(Ordering::Relaxed));
+        update_f64(&self.min, other_min, |new, min| new < min);
+        update_f64(&self.max, other_max, |new, max| new > max);
+
+        #[cfg(feature = "std")]
+        {
+            if let (Some(top), Some(other_top)) = (&self.top, &other.top) {
+                for value in other_top.values() {
+                    top.insert(value, 1);
+                }
+            }
+        }
+    }
+
+    /// Replace the observations in this histogram with a weighted
+    /// combination of its own and those of `other`, scaling each bucket
+    /// count by the corresponding weight and rounding the result. This
+    /// can normalize histograms that were collected over different
+    /// durations before combining them. Unlike `merge`, this must not
+    /// race with concurrent measurements into this histogram.
+    pub fn weighted_merge(&self, other: &Histo, self_weight: f64, other_weight: f64) {
+        #[cfg(not(feature = "saturating"))]
+        assert!(
+            self_weight >= 0. && other_weight >= 0.,
+            "weights must not be negative"
+        );
+        let (self_weight, other_weight) = (self_weight.max(0.), other_weight.max(0.));
+        let other = &*self.compatible(other);
+        let _guard = self.write_guard();
+
+        let mut count = 0;
+        for (dst, src) in self.vals.iter().zip(other.vals.iter()) {
+            let weighted = dst.load(Ordering::Acquire) as f64 * self_weight
+                + src.load(Ordering::Acquire) as f64 * other_weight;
+            let weighted = math::round(weighted) as usize;
+            dst.store(weighted, Ordering::Relaxed);
+            count += weighted as u64;
+        }
+
+        let weigh = |a: &AtomicU64, b: &AtomicU64| {
+            math::round(
+                a.load(Ordering::Acquire) as f64 * self_weight
+                    + b.load(Ordering::Acquire) as f64 * other_weight,
+            ) as u64
+        };
+        self.sum
+            .store(weigh(&self.sum, &other.sum), Ordering::Relaxed);
+        let residual = f64::from_bits(self.residual.load(Ordering::Acquire)) * self_weight
+            + f64::from_bits(other.residual.load(Ordering::Acquire)) * other_weight;
+        self.residual.store(residual.to_bits(), Ordering::Relaxed);
+        let log_sum = f64::from_bits(self.log_sum.load(Ordering::Acquire)) * self_weight
+            + f64::from_bits(other.log_sum.load(Ordering::Acquire)) * other_weight;
+        self.log_sum.store(log_sum.to_bits(), Ordering::Relaxed);
+        let sum_sq = f64::from_bits(self.sum_sq.load(Ordering::Acquire)) * self_weight
+            + f64::from_bits(other.sum_sq.load(Ordering::Acquire)) * other_weight;
+        self.sum_sq.store(sum_sq.to_bits(), Ordering::Relaxed);
+        self.log_count
+            .store(weigh(&self.log_count, &other.log_count), Ordering::Relaxed);
+        self.sq_count
+            .store(weigh(&self.sq_count, &other.sq_count), Ordering::Relaxed);
+
+        if self_weight == 0. {
+            self.min.store(f64::INFINITY.to_bits(), Ordering::Relaxed);
+            self.max
+                .store(f64::NEG_INFINITY.to_bits(), Ordering::Relaxed);
+        }
+        if other_weight > 0. {
+            let other_min = f64::from_bits(other.min.load(Ordering::Acquire));
+            let other_max = f64::from_bits(other.max.load(Ordering::Acquire));
+            update_f64(&self.min, other_min, |new, min| new < min);
+            update_f64(&self.max, other_max, |new, max| new > max);
+        }
+
+        self.count.store(count, Ordering::Release);
+    }
+
+    /// Scale down every bucket count by `factor` [0-1], so that older
+    /// observations fade relative to newer ones. Calling this on a timer
+    /// makes percentiles track recent behavior, like an exponentially
+    /// weighted moving average. Measurements that race with this call are
+    /// preserved, but `min` and `max` still reflect all observations.
+    pub fn decay(&self, factor: f64) {
+        #[cfg(not(feature = "saturating"))]
+        assert!(
+            (0. ..=1.).contains(&factor),
+            "decay factor must be between 0.0 and 1.0"
+        );
+        let factor = if factor >= 0. { factor.min(1.) } else { 0. };
+        let _guard = self.write_guard();
+
+        let mut removed = 0;
+        for val in &self.vals {
+            let count = val.load(Ordering::Acquire);
+            if count == 0 {
+                continue;
+            }
+
+            let decayed = count - math::round(count as f64 * factor) as usize;
+            if decayed != 0 {
+                val.sub(decayed);
+                removed += decayed as u64;
+            }
+        }
+
+        let decay = |total: &AtomicU64| {
+            let current = total.load(Ordering::Acquire);
+            let decayed = current - math::round(current as f64 * factor) as u64;
+            total.fetch_sub(decayed, Ordering::Relaxed);
+        };
+        decay(&self.sum);
+        decay(&self.log_count);
+        decay(&self.sq_count);
+        for total in &[&self.residual, &self.log_sum, &self.sum_sq] {
+            let current = f64::from_bits(total.load(Ordering::Acquire));
+            add_f64(total, current * factor - current);
+        }
+        self.count.fetch_sub(removed, Ordering::Release);
+    }
+
+    /// Return a new histogram with the same shape as this one, but with
+    /// every bucket count scaled so that the total is `target_count`.
+    /// This allows distributions collected from different numbers of
+    /// samples to be compared or combined on equal terms. Each bucket is
+    /// rounded to a whole count, so the resulting total is approximate,
+    /// and buckets that round to zero are dropped.
+    pub fn normalize(&self, target_count: usize) -> Histo {
+        let mut ret = Histo::new(self.scale);
+        ret.valid = self.valid;
+        ret.tracked = self.tracked;
+
+        let count = self.count();
+        if count != 0 {
+            ret.weighted_merge(self, 0., target_count as f64 / count as f64);
+        }
+        ret
+    }
+
+    /// Return a new histogram containing the observations in this one
+    /// that are not in `other`, which is typically an earlier `clone`
+    /// of it. Each bucket, as well as `count` and `sum`, saturates at
+    /// zero. Since the exact extremes of the difference are unknown,
+    /// `min` and `max` are approximated by the lowest and highest
+    /// remaining buckets.
+    pub fn subtract(&self, other: &Histo) -> Histo {
+        let other = &*self.compatible(other);
+
+        let mut ret = Histo::new(self.scale);
+        ret.valid = self.valid;
+        ret.tracked = self.tracked;
+
+        for ((dst, a), b) in ret.vals.iter().zip(self.vals.iter()).zip(other.vals.iter()) {
+            let count = a
+                .load(Ordering::Acquire)
+                .saturating_sub(b.load(Ordering::Acquire));
+            dst.store(count, Ordering::Relaxed);
+        }
+
+        let saturating_sub = |a: &AtomicU64, b: &AtomicU64| {
+            a.load(Ordering::Acquire)
+                .saturating_sub(b.load(Ordering::Acquire))
+        };
+        ret.sum
+            .store(saturating_sub(&self.sum, &other.sum), Ordering::Relaxed);
+        let sum_sq = f64::from_bits(self.sum_sq.load(Ordering::Acquire))
+            - f64::from_bits(other.sum_sq.load(Ordering::Acquire));
+        ret.sum_sq
+            .store(sum_sq.max(0.).to_bits(), Ordering::Relaxed);
+        let residual = f64::from_bits(self.residual.load(Ordering::Acquire))
+            - f64::from_bits(other.residual.load(Ordering::Acquire));
+        ret.residual.store(residual.to_bits(), Ordering::Relaxed);
+        let log_sum = f64::from_bits(self.log_sum.load(Ordering::Acquire))
+            - f64::from_bits(other.log_sum.load(Ordering::Acquire));
+        ret.log_sum.store(log_sum.to_bits(), Ordering::Relaxed);
+        ret.log_count
+            .store(saturating_sub(&self.log_count, &other.log_count), Ordering::Relaxed);
+        ret.sq_count
+            .store(saturating_sub(&self.sq_count, &other.sq_count), Ordering::Relaxed);
+        ret.count
+            .store(saturating_sub(&self.count, &other.count), Ordering::Relaxed);
+
+        ret.approximate_extremes();
+        ret
+    }
+
+    // Returns `other` with the bucketing of this histogram. This panics
+    // unless they already share it, or with the `saturating` feature
+    // re-buckets the observations of `other` by the value of each bucket.
+    fn compatible<'a>(&self, other: &'a Histo) -> Cow<'a, Histo> {
+        if self.scale == other.scale {
+            return Cow::Borrowed(other);
+        }
+
+        #[cfg(not(feature = "saturating"))]
+        panic!(
+            "cannot combine histograms with different bucketing: {:?} and {:?}",
+            self.scale, other.scale
+        );
+
+        #[cfg(feature = "saturating")]
+        Cow::Owned(self.rebucket(other))
+    }
+
+    // Returns the observations of `other` counted in the buckets of this
+    // histogram that contain the values of their original buckets.
+    #[cfg(feature = "saturating")]
+    fn rebucket(&self, other: &Histo) -> Histo {
+        let mut ret = Histo::new(self.scale);
+        ret.valid = other.valid;
+        ret.tracked = other.tracked;
+        #[cfg(feature = "std")]
+        {
+            ret.top = other.top.as_ref().map(top::TopK::empty);
+        }
+
+        let mut dropped = 0;
+        for (idx, src) in other.vals.iter().enumerate() {
+            let count = src.load(Ordering::Acquire);
+            if count == 0 {
+                continue;
+            }
+            let value = other.scale.decompress(idx as u16);
+            if ret
+                .increment(self.scale.compress(value) as usize, count)
+                .is_none()
+            {
+                dropped += count as u64;
+            }
+        }
+        ret.merge_totals(other, dropped);
+        ret
+    }
+
+    // Sets min and max to the values of the lowest and highest nonzero
+    // buckets, for histograms built from bucket counts rather than from
+    // measured values.
+    fn approximate_extremes(&self) {
+        let mut nonzero = self
+            .vals
+            .iter()
+            .enumerate()
+            .filter(|(_, val)| val.load(Ordering::Relaxed) != 0)
+            .map(|(idx, _)| self.scale.decompress(idx as u16));
+
+        if let Some(lowest) = nonzero.next() {
+            let highest = nonzero.last().unwrap_or(lowest);
+            self.min.store(lowest.to_bits(), Ordering::Relaxed);
+            self.max.store(highest.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Zero out all observations so that this histogram may be reused
+    /// without reallocating its buckets. This provides no snapshot
+    /// consistency with concurrent calls to `measure`, and is intended
+    /// to be called during a quiescent phase.
+    pub fn reset(&self) {
+        let _guard = self.write_guard();
+
+        for val in &self.vals {
+            val.store(0, Ordering::Relaxed);
+        }
+
+        self.sum.store(0, Ordering::Relaxed);
+        self.residual.store(0, Ordering::Relaxed);
+        self.log_sum.store(0, Ordering::Relaxed);
+        self.log_count.store(0, Ordering::Relaxed);
+        self.sum_sq.store(0, Ordering::Relaxed);
+        self.sq_count.store(0, Ordering::Relaxed);
+        self.overflows.store(0, Ordering::Relaxed);
+        self.clamped.store(0, Ordering::Relaxed);
+        self.min.store(f64::INFINITY.to_bits(), Ordering::Relaxed);
+        self.max
+            .store(f64::NEG_INFINITY.to_bits(), Ordering::Relaxed);
+        #[cfg(feature = "std")]
+        {
+            if let Some(top) = &self.top {
+                top.clear();
+            }
+        }
+        #[cfg(feature = "reservoir")]
+        {
+            if let Some(reservoir) = &self.reservoir {
+                reservoir.clear();
+            }
+        }
+        self.count.store(0, Ordering::Release);
+    }
+
+    /// Return the largest raw values measured by this histogram in
+    /// descending order, as retained by `HistoBuilder::track_top_k`.
+    /// These are carried over by `merge` and cleared by `reset` and
+    /// `drain`, but are unaffected by `decay`. Returns an empty `Vec`
+    /// if the histogram does not track them.
+    #[cfg(feature = "std")]
+    pub fn top_k(&self) -> Vec<f64> {
+        self.top.as_ref().map_or_else(Vec::new, top::TopK::values)
+    }
+
+    /// Return the exact smallest value measured by this histogram,
+    /// unaffected by bucketing. Returns NAN if no metrics have been
+    /// collected yet.
+    pub fn min(&self) -> f64 {
+        if self.count() == 0 {
+            return f64::NAN;
+        }
+
+        f64::from_bits(self.min.load(Ordering::Acquire))
+    }
+
+    /// Return the exact largest value measured by this histogram,
+    /// unaffected by bucketing. Returns NAN if no metrics have been
+    /// collected yet.
+    pub fn max(&self) -> f64 {
+        if self.count() == 0 {
+            return f64::NAN;
+        }
+
+        f64::from_bits(self.max.load(Ordering::Acquire))
+    }
+
+    /// Return the arithmetic mean of all observations in this histogram.
+    /// This is computed from the raw values passed to `measure` rather
+    /// than the bucketed ones. Returns NAN if no metrics have been
+    /// collected yet.
+    pub fn mean(&self) -> f64 {
+        let count = self.count();
+
+        if count == 0 {
+            return f64::NAN;
+        }
+
+        self.sum_f64() / count as f64
+    }
+
+    /// Return the arithmetic mean as an `f32`, for compact reporting.
+    /// Computation is still performed in `f64`.
+    pub fn mean_f32(&self) -> f32 {
+        self.mean() as f32
+    }
+
+    /// Return the geometric mean of the positive, finite observations in
+    /// this histogram, which suits ratios such as speedups better than
+    /// `mean`. Zero, negative and infinite values are ignored. Returns
+    /// NAN if no positive values have been collected yet, or if this
+    /// histogram was not built with `HistoBuilder::track_geometric_mean`.
+    pub fn geometric_mean(&self) -> f64 {
+        if !self.tracked.log_sum {
+            return f64::NAN;
+        }
+
+        let count = self.log_count.load(Ordering::Acquire);
+
+        if count == 0 {
+            return f64::NAN;
+        }
+
+        let log_sum = f64::from_bits(self.log_sum.load(Ordering::Acquire));
+        math::exp(log_sum / count as f64)
+    }
+
+    /// Return the mean of the observations between the `lower_p` and
+    /// `upper_p` percentiles [0-100], discarding the tails on either side.
+    /// Each observation is represented by the value of its bucket.
+    /// Returns NAN if no observations fall between the percentiles.
+    /// Percentiles outside of [0-100] are clamped, and an `upper_p` below
+    /// `lower_p` is raised to it, after failing a debug assertion.
+    pub fn trimmed_mean(&self, lower_p: f64, upper_p: f64) -> f64 {
+        let (lower_p, upper_p) = clamp_band(lower_p, upper_p);
+
+        let count = self.count() as f64;
+        let lower = count * lower_p / 100.;
+        let upper = count * upper_p / 100.;
+
+        let mut seen = 0.;
+        let mut kept = 0.;
+        let mut total = 0.;
+
+        for (idx, val) in self.vals.iter().enumerate() {
+            let here = val.load(Ordering::Acquire) as f64;
+            if here == 0. {
+                continue;
+            }
+
+            let overlap = (seen + here).min(upper) - seen.max(lower);
+            if overlap > 0. {
+                kept += overlap;
+                total += overlap * self.scale.decompress(idx as u16);
+            }
+
+            seen += here;
+            if seen >= upper {
+                break;
+            }
+        }
+
+        if kept == 0. {
+            return f64::NAN;
+        }
+
+        total / kept
+    }
+
+    /// Return the value of the bucket with the most observations,
+    /// preferring the lowest value on ties. Returns NAN if no metrics
+    /// have been collected yet.
+    pub fn mode(&self) -> f64 {
+        let mut best = None;
+        let mut best_count = 0;
+
+        for (idx, val) in self.vals.iter().enumerate() {
+            let count = val.load(Ordering::Acquire);
+            if count > best_count {
+                best = Some(idx);
+                best_count = count;
+            }
+        }
+
+        best.map_or(f64::NAN, |idx| self.scale.decompress(idx as u16))
+    }
+
+    /// Return the sample variance of all finite observations in this
+    /// histogram, computed from the exact values passed to `measure`
+    /// rather than their buckets. Infinite values are ignored. Returns
+    /// NAN if fewer than two finite metrics have been collected, or if
+    /// this histogram was not built with `HistoBuilder::track_variance`.
+    pub fn variance(&self) -> f64 {
+        let count = self.sq_count.load(Ordering::Acquire);
+
+        if !self.tracked.sum_sq || count < 2 {
+            return f64::NAN;
+        }
+
+        let n = count as f64;
+        let sum = self.sum_f64();
+        let sum_sq = f64::from_bits(self.sum_sq.load(Ordering::Acquire));
+
+        ((sum_sq - sum * sum / n) / (n - 1.)).max(0.)
+    }
+
+    /// Return the sample standard deviation of all finite observations in
+    /// this histogram, as with `variance`. Returns NAN if fewer than two
+    /// finite metrics have been collected, or if variance is not tracked.
+    pub fn stddev(&self) -> f64 {
+        math::sqrt(self.variance())
+    }
+}
+
+/// Lossily compress a value into the index of the bucket that a default
+/// `Histo` records it in, staying within around 0.5% of its magnitude.
+/// The sign of the value is discarded, and values of roughly 1e284 and
+/// above are clamped to the top bucket.
+/// This allows values to be bucketed before being sent elsewhere to be
+/// aggregated.
+pub fn compress(value: f64) -> u16 {
+    Scale::default().compress(value)
+}
+
+/// Return the value represented by a bucket index produced by `compress`.
+pub fn decompress(compressed: u16) -> f64 {
+    Scale::default().decompress(compressed)
+}
+
+// percentile_target returns the cumulative count at which the
+// bucket containing percentile `p` is reached. This is computed with
+// integers, taking `p` in billionths of a percent, so that it remains
+// exact for counts beyond 2^53.
+#[inline]
+fn percentile_target(count: u64, p: f64) -> u64 {
+    const PER_PERCENT: u128 = 1_000_000_000;
+
+    let numerator = count as u128 * math::round(p * PER_PERCENT as f64) as u128;
+    let target = numerator.div_ceil(100 * PER_PERCENT);
+    (target as u64).max(1)
+}
+
+// rounded_sum adds an accumulated residual to an integer sum, saturating
+// rather than wrapping.
+#[inline]
+fn rounded_sum(sum: u64, residual: f64) -> u64 {
+    let residual = math::round(residual);
+    if residual >= 0. {
+        sum.saturating_add(residual as u64)
+    } else {
+        sum.saturating_sub(-residual as u64)
+    }
+}
+
+// add_sum adds `n` occurrences of `rounded` to the integer sum. If the
+// sum wraps, the thread that wrapped it carries 2^64 into the residual,
+// so the total is preserved without retrying the addition.
+#[inline]
+fn add_sum(sum: &AtomicU64, residual: &AtomicU64, rounded: u64, n: u64) {
+    const WRAP: f64 = 18_446_744_073_709_551_616.;
+
+    match rounded.checked_mul(n) {
+        Some(total) => {
+            let old = sum.fetch_add(total, Ordering::Relaxed);
+            if old.checked_add(total).is_none() {
+                add_f64(residual, WRAP);
+            }
+        }
+        None => add_f64(residual, rounded as f64 * n as f64),
+    }
+}
+
+// add_f64 atomically adds `delta` to the f64 stored as bits in `atomic`.
+#[inline]
+fn add_f64(atomic: &AtomicU64, delta: f64) {
+    let mut current = atomic.load(Ordering::Relaxed);
+    loop {
+        let new = (f64::from_bits(current) + delta).to_bits();
+        match atomic.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+// clamp_percentile limits `p` to [0-100], so that a percentile that is
+// out of range yields the nearest valid result rather than a panic in
+// release builds.
+#[inline]
+fn clamp_percentile(p: f64) -> f64 {
+    #[cfg(not(feature = "saturating"))]
+    debug_assert!(
+        (0. ..=100.).contains(&p),
+        "percentiles must be within [0.0, 100.0], got {}",
+        p
+    );
+    p.clamp(0., 100.)
+}
+
+// clamp_band limits a pair of percentiles to [0-100] like
+// `clamp_percentile`, and raises the upper one to at least the lower one.
+#[inline]
+fn clamp_band(low: f64, high: f64) -> (f64, f64) {
+    #[cfg(not(feature = "saturating"))]
+    debug_assert!(
+        low <= high,
+        "the lower percentile {} must not exceed the upper percentile {}",
+        low,
+        high
+    );
+    let low = clamp_percentile(low);
+    (low, clamp_percentile(high).max(low))
+}
+
+// update_f64 atomically replaces the f64 stored as bits in `atomic` with
+// `value` for as long as `replaces(value, current)` holds.
+#[inline]
+fn update_f64<F: Fn(f64, f64) -> bool>(atomic: &AtomicU64, value: f64, replaces: F) {
+    let mut current = atomic.load(Ordering::Relaxed);
+    while replaces(value, f64::from_bits(current)) {
+        match atomic.compare_exchange_weak(
+            current,
+            value.to_bits(),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+// Tracked records which optional statistics a `Histo` maintains, since
+// each costs an extra atomic update on every measurement.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Tracked {
+    // `log_sum` and `log_count`, for `geometric_mean`
+    log_sum: bool,
+    // `sum_sq` and `sq_count`, for `variance` and `stddev`
+    sum_sq: bool,
+}
+
+// Scale describes how values are mapped onto buckets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scale {
+    precision: f64,
+    buckets: usize,
+    signed: bool,
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale {
+            precision: PRECISION,
+            buckets: BUCKETS,
+            signed: false,
+        }
+    }
+}
+
+impl Scale {
+    // compress takes a value and lossily shrinks it to an u16 to facilitate
+    // bucketing of histogram values, staying roughly within 1% of the true
+    // value. With the default precision, values of 1e284 and above (1e142
+    // when signed) are clamped to the outermost bucket, and it is
+    // inaccurate for values closer to 0 than +/- 0.51 or +/- math.Inf.
+    // When signed, negative values are mirrored below the midpoint of the
+    // keyspace so that ascending keys correspond to ascending values.
+    #[inline]
+    fn compress<T: Into<f64>>(&self, value: T) -> u16 {
+        let value: f64 = value.into();
+        self.try_compress(value)
+            .unwrap_or_else(|| self.saturate(value))
+    }
+
+    // try_compress is compress, except that it returns None rather than
+    // clamping values that are beyond the outermost buckets.
+    #[inline]
+    fn try_compress(&self, value: f64) -> Option<u16> {
+        let abs = value.abs();
+        let boosted = 1. + abs;
+        let ln = math::ln(boosted);
+        let compressed = self.precision * ln + 0.5;
+
+        if self.signed {
+            let half = (self.buckets / 2) as f64;
+            if compressed >= half || compressed.is_nan() {
+                None
+            } else if value.is_sign_negative() {
+                Some((half - 1. - math::trunc(compressed)) as u16)
+            } else {
+                Some((half + compressed) as u16)
+            }
+        } else if compressed < self.buckets as f64 {
+            Some(compressed as u16)
+        } else {
+            None
+        }
+    }
+
+    // saturate returns the outermost bucket on the side of the given value
+    #[inline]
+    fn saturate(&self, value: f64) -> u16 {
+        if self.signed && value.is_sign_negative() {
+            0
+        } else {
+            (self.buckets - 1) as u16
+        }
+    }
+
+    // decompress takes a lossily shrunken u16 and returns an f64 within 1% of
+    // the original passed to compress.
+    #[inline]
+    fn decompress(&self, compressed: u16) -> f64 {
+        if self.signed {
+            let half = (self.buckets / 2) as u16;
+            if compressed < half {
+                -self.decompress_unsigned(half - 1 - compressed)
+            } else {
+                self.decompress_unsigned(compressed - half)
+            }
+        } else {
+            self.decompress_unsigned(compressed)
+        }
+    }
+
+    #[inline]
+    fn decompress_unsigned(&self, compressed: u16) -> f64 {
+        let unboosted = compressed as f64 / self.precision;
+        math::exp(unboosted) - 1.
+    }
+
+    // bounds returns the lowest and highest values that compress to the
+    // given bucket. Because compress rounds, the decompressed value of a
+    // bucket lies between these bounds rather than on either of them.
+    fn bounds(&self, compressed: u16) -> (f64, f64) {
+        if self.signed {
+            let half = (self.buckets / 2) as u16;
+            if compressed < half {
+                let (low, high) = self.bounds_unsigned(half - 1 - compressed);
+                (-high, -low)
+            } else {
+                self.bounds_unsigned(compressed - half)
+            }
+        } else {
+            self.bounds_unsigned(compressed)
+        }
+    }
+
+    fn bounds_unsigned(&self, compressed: u16) -> (f64, f64) {
+        let edge = |unboosted: f64| (math::exp(unboosted / self.precision) - 1.).max(0.);
+        let compressed = compressed as f64;
+        (edge(compressed - 0.5), edge(compressed + 0.5))
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn it_works() {
+    let c = Histo::default();
+    assert_eq!(c.measure(2), 1);
+    assert_eq!(c.measure(2), 2);
+    assert_eq!(c.measure(3), 1);
+    assert_eq!(c.measure(3), 2);
+    assert_eq!(c.measure(4), 1);
+    assert_eq!(c.percentile(0.).round() as usize, 2);
+    assert_eq!(c.percentile(40.).round() as usize, 2);
+    assert_eq!(c.percentile(40.1).round() as usize, 3);
+    assert_eq!(c.percentile(80.).round() as usize, 3);
+    assert_eq!(c.percentile(80.1).round() as usize, 4);
+    assert_eq!(c.percentile(100.).round() as usize, 4);
+    #[cfg(feature = "std")]
+    c.print_percentiles();
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn high_percentiles() {
+    let c = Histo::default();
+    for _ in 0..9000 {
+        c.measure(10);
+    }
+    for _ in 0..900 {
+        c.measure(25);
+    }
+    for _ in 0..90 {
+        c.measure(33);
+    }
+    for _ in 0..9 {
+        c.measure(47);
+    }
+    c.measure(500);
+    assert_eq!(c.percentile(0.).round() as usize, 10);
+    assert_eq!(c.percentile(99.).round() as usize, 25);
+    assert_eq!(c.percentile(99.89).round() as usize, 33);
+    assert_eq!(c.percentile(99.91).round() as usize, 47);
+    assert_eq!(c.percentile(99.99).round() as usize, 47);
+    assert_eq!(c.percentile(100.).round() as usize, 502);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn multithreaded() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let h = Arc::new(Histo::default());
+    let mut threads = vec![];
+
+    for _ in 0..10 {
+        let h = h.clone();
+        threads.push(thread::spawn(move || {
+            h.measure(20);
+        }));
+    }
+
+    for t in threads.into_iter() {
+        t.join().unwrap();
+    }
+
+    assert_eq!(h.percentile(50.).round() as usize, 20);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn mean() {
+    let c = Histo::default();
+    assert!(c.mean().is_nan());
+    c.measure(3);
+    c.measure(500);
+    c.measure(47);
+    assert_eq!(c.mean(), 550. / 3.);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn merge() {
+    let a = Histo::default();
+    let b = Histo::default();
+    let all = Histo::default();
+    for i in 0..100 {
+        a.measure(i);
+        all.measure(i);
+    }
+    for i in 1000..1100 {
+        b.measure(i);
+        all.measure(i);
+    }
+
+    a.merge(&b);
+
+    assert_eq!(a.count(), all.count());
+    assert_eq!(a.sum(), all.sum());
+    for p in &[0., 25., 50., 75., 99., 100.] {
+        assert_eq!(a.percentile(*p), all.percentile(*p));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn reset() {
+    let c = Histo::default();
+    for _ in 0..100 {
+        c.measure(1000);
+    }
+
+    let idx = c.scale.compress(1000) as usize;
+    c.vals[idx].store(Bucket::MAX, Ordering::Relaxed);
+    c.measure(1000);
+    assert_eq!(c.overflow_count(), 1);
+    c.measure(1e300);
+    assert_eq!(c.clamped_count(), 1);
+
+    c.reset();
+    assert_eq!(c.count(), 0);
+    assert_eq!(c.sum(), 0);
+    assert_eq!(c.overflow_count(), 0);
+    assert_eq!(c.clamped_count(), 0);
+    assert!(c.percentile(50.).is_nan());
+
+    c.measure(2);
+    c.measure(3);
+    assert_eq!(c.count(), 2);
+    assert_eq!(c.sum(), 5);
+    assert_eq!(c.percentile(0.).round() as usize, 2);
+    assert_eq!(c.percentile(100.).round() as usize, 3);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn signed() {
+    let c = Histo::signed();
+    for i in -100..=100 {
+        c.measure(i);
+    }
+    assert_eq!(c.percentile(0.).round() as i64, -100);
+    assert_eq!(c.percentile(50.).round() as i64, 0);
+    assert_eq!(c.percentile(100.).round() as i64, 100);
+
+    let unsigned = Histo::default();
+    unsigned.measure(-10);
+    assert_eq!(unsigned.percentile(50.).round() as i64, 10);
+
+    let c = Histo::signed();
+    c.measure(-10);
+    c.measure(40);
+    assert_eq!(c.sum(), 30);
+    assert_eq!(c.sum_f64(), 30.);
+    assert_eq!(c.mean(), 15.);
+
+    c.reset();
+    c.measure(-10);
+    c.measure(4);
+    assert_eq!(c.sum(), 0);
+    assert_eq!(c.sum_f64(), -6.);
+    assert_eq!(c.mean(), c.sum_f64() / c.count() as f64);
+    assert_eq!(c.mean(), -3.);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn min_max() {
+    let c = Histo::default();
+    assert!(c.min().is_nan());
+    assert!(c.max().is_nan());
+
+    c.measure(3);
+    c.measure(500);
+    c.measure(47);
+    assert_eq!(c.min(), 3.);
+    assert_eq!(c.max(), 500.);
+    assert_ne!(c.percentile(100.), 500.);
+
+    let other = Histo::default();
+    other.measure(1);
+    c.merge(&other);
+    assert_eq!(c.min(), 1.);
+    assert_eq!(c.max(), 500.);
+
+    c.reset();
+    assert!(c.min().is_nan());
+    c.measure(7);
+    assert_eq!(c.min(), 7.);
+    assert_eq!(c.max(), 7.);
+}
+
+#[test]
+fn buckets() {
+    let c = Histo::default();
+    assert_eq!(c.buckets().count(), 0);
+
+    for i in 0..1000 {
+        c.measure(i % 97);
+    }
+
+    let total: usize = c.buckets().map(|(_, count)| count).sum();
+    assert_eq!(total as u64, c.count());
+
+    let values: Vec<f64> = c.buckets().map(|(value, _)| value).collect();
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn builder() {
+    assert_eq!(Histo::default().vals.len(), BUCKETS);
+
+    let c = Histo::builder().precision(10.).build();
+    assert!(c.vals.len() < BUCKETS / 8);
+    c.measure(1000);
+    c.measure(1e100);
+    assert!((c.percentile(0.) - 1000.).abs() < 1000. * 0.1);
+    assert!((c.percentile(100.) - 1e100).abs() < 1e100 * 0.1);
+
+    let c = Histo::builder().max_buckets(4096).build();
+    assert_eq!(c.vals.len(), 4096);
+    c.measure(10);
+    assert_eq!(c.percentile(50.).round() as usize, 10);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn quantiles() {
+    let c = Histo::default();
+    assert!(c.quantiles(&[50.]).iter().all(|v| v.is_nan()));
+
+    for i in 0..10_000 {
+        c.measure(i % 1234);
+    }
+
+    let individual: Vec<f64> = PERCENTILES.iter().map(|p| c.percentile(*p)).collect();
+    assert_eq!(c.quantiles(&PERCENTILES), individual);
+
+    let unsorted = [99., 0., 50., 99.];
+    let individual: Vec<f64> = unsorted.iter().map(|p| c.percentile(*p)).collect();
+    assert_eq!(c.quantiles(&unsorted), individual);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn non_finite() {
+    let c = Histo::default();
+    assert_eq!(c.measure(f64::NAN), 0);
+    assert_eq!(c.count(), 0);
+
+    c.measure(10);
+    c.measure(f64::INFINITY);
+    c.measure(f64::NEG_INFINITY);
+    assert_eq!(c.count(), 3);
+    assert_eq!(c.sum(), 10);
+    assert_eq!(c.percentile(0.).round() as usize, 10);
+    assert!(c.percentile(100.) > 1e200);
+
+    let c = Histo::signed();
+    c.measure(f64::NEG_INFINITY);
+    c.measure(f64::INFINITY);
+    c.measure(f64::NAN);
+    assert_eq!(c.count(), 2);
+    assert!(c.percentile(0.) < -1e100);
+    assert!(c.percentile(100.) > 1e100);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn large_sum() {
+    let c = Histo::default();
+    for _ in 0..4 {
+        c.measure(2_000_000_000);
+    }
+    assert_eq!(c.sum(), 8_000_000_000);
+    assert!(c.sum() > u64::from(u32::MAX));
+    assert_eq!(c.mean(), 2e9);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn clone() {
+    let c = Histo::default();
+    for i in 0..100 {
+        c.measure(i);
+    }
+
+    let snapshot = c.clone();
+    let before: Vec<f64> = PERCENTILES
+        .iter()
+        .map(|p| snapshot.percentile(*p))
+        .collect();
+
+    for _ in 0..1000 {
+        c.measure(5000);
+    }
+
+    let after: Vec<f64> = PERCENTILES
+        .iter()
+        .map(|p| snapshot.percentile(*p))
+        .collect();
+    assert_eq!(before, after);
+    assert_eq!(snapshot.count(), 100);
+    assert_eq!(snapshot.max(), 99.);
+    assert_eq!(c.count(), 1100);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn stddev() {
+    let untracked = Histo::default();
+    for v in &[1, 2, 3] {
+        untracked.measure(*v);
+    }
+    assert!(untracked.variance().is_nan());
+
+    let c = Histo::builder().track_variance(true).build();
+    c.measure(10);
+    assert!(c.variance().is_nan());
+    assert!(c.stddev().is_nan());
+
+    c.reset();
+    for v in &[2, 4, 4, 4, 5, 5, 7, 9] {
+        c.measure(*v);
+    }
+    assert!((c.variance() - 32. / 7.).abs() < 1e-9);
+    assert!((c.stddev() - (32f64 / 7.).sqrt()).abs() < 1e-9);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn variance_ignores_infinite() {
+    let builder = Histo::builder().track_variance(true);
+    let c = builder.clone().build();
+    c.measure(f64::INFINITY);
+    c.measure(5);
+    assert!(c.variance().is_nan());
+
+    for v in &[2, 4, 4, 4, 5, 7, 9] {
+        c.measure(*v);
+    }
+    c.measure(f64::INFINITY);
+    assert_eq!(c.count(), 10);
+    assert!((c.variance() - 32. / 7.).abs() < 1e-9);
+
+    let mut batch = c.batch();
+    batch.add(f64::INFINITY);
+    c.apply_batch(&mut batch);
+    let merged = builder.build();
+    merged.merge(&c);
+    assert!((merged.variance() - 32. / 7.).abs() < 1e-9);
+    assert_eq!(Histo::from_bytes(&c.to_bytes()).unwrap().variance(), c.variance());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn stddev_nanoseconds() {
+    // squares of 10ms and 20ms in nanoseconds overflow a u64 sum
+    let c = Histo::builder().track_variance(true).build();
+    for i in 0..100_000 {
+        c.measure(if i % 2 == 0 { 10_000_000 } else { 20_000_000 });
+    }
+
+    let expected = 5_000_000. * (100_000f64 / 99_999.).sqrt();
+    assert!((c.stddev() / expected - 1.).abs() < 1e-6, "{}", c.stddev());
+
+    let merged = Histo::builder().track_variance(true).build();
+    merged.merge(&c);
+    assert_eq!(merged.stddev(), c.stddev());
+    let decoded = Histo::from_bytes(&c.to_bytes()).unwrap();
+    assert_eq!(decoded.stddev(), c.stddev());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn stddev_signed() {
+    let c = Histo::builder().signed(true).track_variance(true).build();
+    c.measure(-10);
+    c.measure(4);
+    assert!((c.variance() - 98.).abs() < 1e-9, "{}", c.variance());
+
+    let fractional = Histo::builder().track_variance(true).build();
+    for v in &[0.25, 0.75] {
+        fractional.measure(*v);
+    }
+    assert!((fractional.variance() - 0.125).abs() < 1e-12);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn eq() {
+    let a = Histo::default();
+    let b = Histo::default();
+    assert_eq!(a, b);
+
+    for i in 0..100 {
+        a.measure(i);
+        b.measure(99 - i);
+    }
+    assert_eq!(a, b);
+
+    b.measure(1000);
+    assert_ne!(a, b);
+
+    assert_ne!(Histo::default(), Histo::signed());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn rank() {
+    let c = Histo::default();
+    assert!(c.rank(10.).is_nan());
+
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+
+    assert_eq!(c.rank(0.), 0.);
+    assert_eq!(c.rank(1000.), 100.);
+    assert!((c.rank(500.) - 50.).abs() < 1.);
+
+    for p in &[10., 25., 50., 75., 90., 99.] {
+        let value = c.percentile(*p);
+        assert!((c.rank(value) - p).abs() < 1.);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn format_percentiles() {
+    let c = Histo::default();
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+
+    let custom = c.format_percentiles(&[50., 99., 99.9]);
+    assert_eq!(custom, "Histogram[(50 -> 502) (99 -> 991) (99.9 -> 1001) ]");
+    assert_eq!(format!("{:?}", c), c.format_percentiles(&PERCENTILES));
+    #[cfg(feature = "std")]
+    c.print_percentiles_custom(&[50., 99., 99.9]);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn durations() {
+    let c = Histo::default();
+    assert_eq!(c.percentile_duration(50.), Duration::default());
+
+    for ms in 1..=9 {
+        c.measure_duration(Duration::from_millis(ms));
+    }
+
+    let median = c.percentile_duration(50.);
+    let expected = Duration::from_millis(5);
+    assert!(median.max(expected) - median.min(expected) < expected / 100);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_100() {
+    let c = Histo::default();
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+    c.measure(1_000_000);
+
+    let expected = c.scale.decompress(c.scale.compress(1_000_000));
+    assert_eq!(c.percentile(100.), expected);
+
+    // simulate a reader that loaded the count before a concurrent
+    // measurement of the largest value completed
+    c.count.fetch_sub(1, Ordering::Relaxed);
+    assert_eq!(c.percentile(100.), expected);
+    assert_eq!(c.quantiles(&[50., 100.])[1], expected);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn add_assign() {
+    let mut c = Histo::default();
+    c += 5.0;
+    c += 7u64;
+    assert_eq!(c.count(), 2);
+
+    let shared = std::sync::Arc::new(c);
+    let mut h = &*shared;
+    h += 5.0;
+    h += 9u64;
+    assert_eq!(shared.count(), 4);
+    assert_eq!(shared.sum(), 26);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn try_percentile() {
+    let c = Histo::default();
+    assert_eq!(c.try_percentile(50.), None);
+
+    c.measure(10);
+    assert_eq!(c.try_percentile(50.).map(f64::round), Some(10.));
+    assert_eq!(c.try_percentile(100.1), None);
+    assert_eq!(c.try_percentile(-1.), None);
+    assert_eq!(c.try_percentile(f64::NAN), None);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_relaxed() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let h = Arc::new(Histo::default());
+    assert!(h.percentile_relaxed(50.).is_nan());
+
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let h = h.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    h.measure(t * 1000 + i);
+                }
+            })
+        })
+        .collect();
+
+    for t in threads.into_iter() {
+        t.join().unwrap();
+    }
+
+    for p in &PERCENTILES {
+        assert_eq!(h.percentile_relaxed(*p), h.percentile(*p));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn subtract() {
+    let c = Histo::default();
+    for i in 0..100 {
+        c.measure(i);
+    }
+
+    let base = c.clone();
+    for _ in 0..10 {
+        c.measure(1000);
+    }
+
+    let delta = c.subtract(&base);
+    assert_eq!(delta.count(), 10);
+    assert_eq!(delta.sum(), 10_000);
+    assert_eq!(delta.percentile(0.).round() as usize, 1001);
+    assert_eq!(delta.percentile(100.).round() as usize, 1001);
+    assert_eq!(delta.min(), delta.percentile(0.));
+
+    let empty = base.subtract(&c);
+    assert_eq!(empty.count(), 0);
+    assert_eq!(empty.buckets().count(), 0);
+}
+
+#[test]
+fn send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Histo>();
+    assert_send_sync::<Snapshot>();
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn mode() {
+    let c = Histo::default();
+    assert!(c.mode().is_nan());
+
+    for i in 0..1000 {
+        c.measure(i);
+    }
+    for _ in 0..50 {
+        c.measure(700);
+    }
+    assert_eq!(c.mode(), c.scale.decompress(c.scale.compress(700)));
+
+    let tie = Histo::default();
+    tie.measure(30);
+    tie.measure(20);
+    assert_eq!(tie.mode().round() as usize, 20);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn overflow() {
+    let c = Histo::default();
+    let idx = c.scale.compress(10) as usize;
+    c.vals[idx].store(Bucket::MAX - 1, Ordering::Relaxed);
+
+    assert_eq!(c.measure(10), Bucket::MAX);
+    assert_eq!(c.overflow_count(), 0);
+    assert_eq!(c.count(), 1);
+
+    assert_eq!(c.measure(10), usize::MAX);
+    assert_eq!(c.overflow_count(), 1);
+    assert_eq!(c.count(), 1);
+    assert_eq!(c.vals[idx].load(Ordering::Relaxed), Bucket::MAX);
+
+    c.measure(20);
+    assert_eq!(c.overflow_count(), 1);
+    assert_eq!(c.count(), 2);
+}
+
+#[test]
+#[cfg(all(feature = "std", not(feature = "disable")))]
+fn concurrent_overflow() {
+    use std::thread;
+
+    let c = Histo::default();
+    let idx = c.scale.compress(10) as usize;
+    c.vals[idx].store(Bucket::MAX - 100, Ordering::Relaxed);
+
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..100_000 {
+                    c.measure(10);
+                }
+            });
+        }
+    });
+
+    assert_eq!(c.vals[idx].load(Ordering::Relaxed), Bucket::MAX);
+    assert_eq!(c.count(), 100);
+    assert_eq!(c.overflow_count(), 800_000 - 100);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn measure_n() {
+    let a = Histo::default();
+    let b = Histo::builder().track_variance(true).build();
+    for _ in 0..1000 {
+        a.measure(10);
+    }
+    assert_eq!(b.measure_n(10, 1000), 1000);
+    assert_eq!(a, b);
+    assert_eq!(b.variance(), 0.);
+
+    assert_eq!(b.measure_n(20, 0), 0);
+    assert_eq!(b.count(), 1000);
+    assert_eq!(b.max(), 10.);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_interpolated() {
+    let c = Histo::default();
+    assert!(c.percentile_interpolated(50.).is_nan());
+
+    for _ in 0..100 {
+        c.measure(1000);
+    }
+    for _ in 0..100 {
+        c.measure(5000);
+    }
+
+    let mut last = f64::NEG_INFINITY;
+    for p in 0..=100 {
+        let p = p as f64;
+        let discrete = c.percentile(p);
+        let interpolated = c.percentile_interpolated(p);
+        let (low, high) = c.scale.bounds(c.scale.compress(discrete));
+
+        assert!(low <= discrete && discrete <= high);
+        assert!(low <= interpolated && interpolated <= high);
+        assert!(interpolated >= last);
+        last = interpolated;
+    }
+
+    let (low, high) = c.scale.bounds(c.scale.compress(1000));
+    assert!(c.percentile_interpolated(10.) < c.percentile_interpolated(40.));
+    assert!((c.percentile_interpolated(25.) - (low + high) / 2.).abs() < 1e-9);
+
+    // the rank is computed exactly, as it is by `percentile`
+    let c = Histo::default();
+    for i in 1..=3 {
+        c.measure(i);
+    }
+    for &p in &[100. / 3., 200. / 3.] {
+        let (low, high) = c.scale.bounds(c.scale.compress(c.percentile(p)));
+        let interpolated = c.percentile_interpolated(p);
+        assert!(low <= interpolated && interpolated <= high, "p{}", p);
+    }
+}
+
+#[test]
+fn bounds() {
+    for scale in &[Scale::default(), Histo::signed().scale] {
+        for idx in 0..u16::MAX {
+            let (low, high) = scale.bounds(idx);
+            let (next_low, _) = scale.bounds(idx + 1);
+            assert!(low <= scale.decompress(idx) && scale.decompress(idx) <= high);
+            assert!(next_low == high || (next_low - high).abs() <= high.abs() * 1e-12);
+        }
+    }
+}
+
+#[test]
+fn compress_roundtrip() {
+    let mut value = 1.;
+    while value < 1e200 {
+        let roundtrip = decompress(compress(value));
+        assert!((roundtrip - value).abs() / value < 0.01);
+        value *= 1.37;
+    }
+    assert_eq!(decompress(compress(-50.)), decompress(compress(50.)));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn from_buckets() {
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i * 3);
+    }
+
+    let exported: Vec<(u16, usize)> = c.buckets().map(|(value, n)| (compress(value), n)).collect();
+    let rebuilt = Histo::from_buckets(exported);
+
+    assert_eq!(rebuilt.count(), c.count());
+    assert!((rebuilt.sum() as f64 - c.sum() as f64).abs() / (c.sum() as f64) < 0.01);
+    assert_eq!(rebuilt.vals.len(), c.vals.len());
+    for (a, b) in rebuilt.vals.iter().zip(c.vals.iter()) {
+        assert_eq!(a.load(Ordering::Relaxed), b.load(Ordering::Relaxed));
+    }
+    for p in &PERCENTILES {
+        assert_eq!(rebuilt.percentile(*p), c.percentile(*p));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn median() {
+    let c = Histo::default();
+    assert!(c.median().is_nan());
+    for i in 0..100 {
+        c.measure(i);
+    }
+    assert_eq!(c.median(), c.percentile(50.));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn trimmed_mean() {
+    let c = Histo::default();
+    assert!(c.trimmed_mean(1., 99.).is_nan());
+
+    for _ in 0..990 {
+        c.measure(100);
+    }
+    for _ in 0..10 {
+        c.measure(1_000_000);
+    }
+
+    let bulk = c.percentile(50.);
+    assert!(c.mean() > 10. * bulk);
+    assert!((c.trimmed_mean(1., 99.) - bulk).abs() < 1e-9);
+    assert!((c.trimmed_mean(0., 100.) - c.mean()).abs() / c.mean() < 0.01);
+    assert!(c.trimmed_mean(50., 50.).is_nan());
+}
+
+#[test]
+#[cfg_attr(
+    all(debug_assertions, not(feature = "saturating")),
+    should_panic(expected = "must not exceed")
+)]
+fn trimmed_mean_inverted() {
+    let c = Histo::default();
+    for i in 1..=100 {
+        c.measure(i);
+    }
+
+    assert!(c.trimmed_mean(75., 25.).is_nan());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn weighted_merge() {
+    let a = Histo::default();
+    let b = Histo::default();
+    for _ in 0..100 {
+        a.measure(10);
+        b.measure(1000);
+    }
+
+    let combined = a.clone();
+    combined.weighted_merge(&b, 2., 1.);
+    assert_eq!(combined.count(), 300);
+    assert_eq!(combined.sum(), 2 * 1000 + 100_000);
+    assert_eq!(combined.percentile(60.).round() as usize, 10);
+    assert_eq!(combined.percentile(70.).round() as usize, 1001);
+    assert_eq!(combined.min(), 10.);
+    assert_eq!(combined.max(), 1000.);
+
+    let combined = a.clone();
+    combined.weighted_merge(&b, 0.5, 1.);
+    assert_eq!(combined.count(), 150);
+    assert_eq!(combined.median().round() as usize, 1001);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn cdf() {
+    let c = Histo::default();
+    assert_eq!(c.cdf().count(), 0);
+
+    for i in 0..1000 {
+        c.measure(i % 300);
+    }
+
+    let cdf: Vec<(f64, f64)> = c.cdf().collect();
+    assert!(cdf.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 <= w[1].1));
+    assert_eq!(cdf.last().unwrap().1, 1.);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn clamping() {
+    let c = Histo::default();
+    c.measure(1e300);
+    c.measure(f64::INFINITY);
+    c.measure(10);
+    assert_eq!(c.count(), 3);
+    assert_eq!(c.clamped_count(), 2);
+    assert_eq!(c.vals[BUCKETS - 1].load(Ordering::Relaxed), 2);
+
+    let c = Histo::signed();
+    c.measure(1e200);
+    c.measure(-1e200);
+    assert_eq!(c.clamped_count(), 2);
+    assert_eq!(c.vals[0].load(Ordering::Relaxed), 1);
+    assert_eq!(c.vals[BUCKETS - 1].load(Ordering::Relaxed), 1);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn measure_u64() {
+    let c = Histo::default();
+    let big = (1 << 53) + 1;
+    c.measure_u64(big);
+    c.measure_u64(big);
+    assert_eq!(c.sum(), 2 * big);
+    assert_eq!(c.count(), 2);
+
+    let lossy = Histo::default();
+    lossy.measure(big as f64);
+    lossy.measure(big as f64);
+    assert_ne!(lossy.sum(), 2 * big);
+    assert_eq!(lossy.percentile(50.), c.percentile(50.));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_range() {
+    let c = Histo::default();
+    let (low, high) = c.percentile_range(25., 75.);
+    assert!(low.is_nan() && high.is_nan());
+
+    for i in 0..1000 {
+        c.measure(i);
+    }
+
+    for &(low, high) in &[(25., 75.), (5., 95.), (0., 100.), (50., 50.)] {
+        assert_eq!(c.percentile_range(low, high), (c.percentile(low), c.percentile(high)));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+#[cfg_attr(
+    all(debug_assertions, not(feature = "saturating")),
+    should_panic(expected = "must not exceed")
+)]
+fn percentile_range_inverted() {
+    let c = Histo::default();
+    for i in 1..=100 {
+        c.measure(i);
+    }
+
+    let (low, high) = c.percentile_range(75., 25.);
+    assert_eq!(low, c.percentile(75.));
+    assert_eq!(high, low);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn decay() {
+    let c = Histo::default();
+    for _ in 0..1000 {
+        c.measure(100);
+    }
+
+    c.decay(0.5);
+    assert_eq!(c.count(), 500);
+    assert_eq!(c.sum(), 50_000);
+
+    c.decay(0.001);
+    assert_eq!(c.count(), 1);
+    for _ in 0..1000 {
+        c.measure(5000);
+    }
+    let recent = c.scale.decompress(c.scale.compress(5000));
+    assert_eq!(c.percentile(1.), recent);
+    assert_eq!(c.median(), recent);
+
+    c.decay(0.);
+    assert_eq!(c.count(), 0);
+    assert!(c.median().is_nan());
+}
+
+#[test]
+#[cfg(not(feature = "std"))]
+fn no_std() {
+    let c = Histo::default();
+    for i in 1..=100 {
+        c.measure(i);
+    }
+    assert_eq!(c.count(), 100);
+    assert_eq!(c.percentile(0.).round() as usize, 1);
+    assert_eq!(c.percentile(100.).round() as usize, 100);
+    assert_eq!(c.median(), c.scale.decompress(c.scale.compress(50)));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn bucket_ranges() {
+    let c = Histo::default();
+    for i in 1..=10 {
+        c.measure(i * 100);
+    }
+
+    let ranges: Vec<_> = c.bucket_ranges().collect();
+    assert_eq!(ranges.len(), 10);
+    assert_eq!(ranges.iter().map(|r| r.2).sum::<usize>(), 10);
+    for (low, high, _) in &ranges {
+        assert!(low < high);
+    }
+
+    let idx = c.scale.compress(100);
+    let (low, high) = c.bucket_bounds(idx);
+    let (next_low, _) = c.bucket_bounds(idx + 1);
+    assert_eq!(ranges[0], (low, high, 1));
+    assert!((next_low - high).abs() <= high * 1e-12);
+    assert!(low <= 100. && 100. <= high);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_with_error() {
+    let c = Histo::default();
+    assert!(c.percentile_with_error(50.).0.is_nan());
+
+    c.measure(10);
+    c.measure(1000);
+    c.measure(100_000);
+
+    let (small, small_err) = c.percentile_with_error(0.);
+    let (medium, medium_err) = c.percentile_with_error(50.);
+    let (large, large_err) = c.percentile_with_error(100.);
+    assert_eq!(medium, c.percentile(50.));
+    assert!(small_err < medium_err && medium_err < large_err);
+
+    // log bucketing keeps the relative error roughly constant
+    for (value, err) in &[(small, small_err), (medium, medium_err), (large, large_err)] {
+        assert!(err / value < 0.01);
+    }
+    assert!((large_err / large - medium_err / medium).abs() < 1e-4);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn observe() {
+    let measured = Histo::default();
+    let observed = Histo::default();
+    for i in 0..1000 {
+        measured.measure(i);
+        observed.observe(i);
+    }
+    measured.measure(f64::NAN);
+    observed.observe(f64::NAN);
+
+    assert_eq!(measured, observed);
+    assert_eq!(measured.min(), observed.min());
+    assert_eq!(measured.max(), observed.max());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn add() {
+    let (a, b, c) = (Histo::default(), Histo::default(), Histo::default());
+    for i in 0..100 {
+        a.measure(i);
+        b.measure(i + 100);
+        c.measure(i + 200);
+    }
+
+    let mut combined = &a + &b;
+    combined += &c;
+    assert_eq!(a.count(), 100);
+    assert_eq!(combined.count(), 300);
+    assert_eq!(combined.sum(), (0..300).sum::<u64>());
+    assert_eq!(combined.percentile(0.), a.percentile(0.));
+    assert_eq!(combined.median(), b.median());
+    assert_eq!(combined.percentile(100.), c.percentile(100.));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn sum_histos() {
+    let single = Histo::default();
+    let workers: Vec<Histo> = (0..4)
+        .map(|worker| {
+            let h = Histo::default();
+            for i in 0..250 {
+                h.measure(worker * 250 + i);
+                single.measure(worker * 250 + i);
+            }
+            h
+        })
+        .collect();
+
+    let by_ref: Histo = workers.iter().sum();
+    assert_eq!(by_ref, single);
+    assert_eq!(workers[0].count(), 250);
+
+    let owned: Histo = workers.into_iter().sum();
+    assert_eq!(owned, single);
+    assert_eq!(owned.quantiles(&PERCENTILES), single.quantiles(&PERCENTILES));
+
+    let empty: Histo = Vec::<Histo>::new().into_iter().sum();
+    assert_eq!(empty.count(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_count() {
+    let c = Histo::default();
+    assert_eq!(c.percentile_count(50.), 0);
+
+    for i in 1..=10 {
+        for _ in 0..10 {
+            c.measure(i * 100);
+        }
+    }
+
+    assert_eq!(c.percentile_count(0.), 10);
+    assert_eq!(c.percentile_count(50.), 50);
+    assert_eq!(c.percentile_count(55.), 60);
+    assert_eq!(c.percentile_count(100.), c.count() as usize);
+
+    let mut last = 0;
+    for p in 0..=100 {
+        let rank = c.percentile_count(p as f64);
+        assert!(rank >= last);
+        last = rank;
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn fractional_sum() {
+    let c = Histo::default();
+    for _ in 0..1000 {
+        c.measure(0.4);
+    }
+    assert!((c.mean() - 0.4).abs() < 1e-9);
+    assert_eq!(c.sum(), 400);
+
+    let merged = Histo::default();
+    merged.merge(&c);
+    merged.measure_u64(u64::MAX - 1000);
+    assert_eq!(merged.sum(), u64::MAX - 600);
+
+    c.reset();
+    assert_eq!(c.sum(), 0);
+    c.measure(2.5);
+    c.measure(-0.5);
+    assert!((c.mean() - 1.).abs() < 1e-9);
+
+    // totals beyond u64 saturate the sum rather than wrapping it
+    let big = Histo::default();
+    big.measure_n(1e19, 2);
+    assert_eq!(big.sum(), u64::MAX);
+    assert!((big.mean() - 1e19).abs() / 1e19 < 1e-9);
+    big.measure(1e19);
+    assert_eq!(big.sum(), u64::MAX);
+    assert!((big.mean() - 1e19).abs() / 1e19 < 1e-9);
+
+    let merged = Histo::default();
+    merged.merge(&big);
+    merged.merge(&big);
+    assert_eq!(merged.sum(), u64::MAX);
+    assert!((merged.mean() - 1e19).abs() / 1e19 < 1e-9);
+}
+
+#[test]
+fn relative_error() {
+    let default = Histo::default();
+    let fine = Histo::builder().relative_error(0.0005).build();
+    let coarse = Histo::builder().log_base(1.1).build();
+
+    let width = |h: &Histo, value: f64| {
+        let (low, high) = h.bucket_bounds(h.scale.compress(value));
+        high - low
+    };
+    for &value in &[10., 1000., 1e6] {
+        assert!(width(&fine, value) < width(&default, value) / 5.);
+        assert!(width(&coarse, value) > width(&default, value) * 5.);
+        assert!(width(&fine, value) / 2. <= (1. + value) * 0.0005 * 1.01);
+        assert!((width(&coarse, value) / (1. + value) - 0.1).abs() < 0.01);
+    }
+
+    let default_error = Histo::builder().relative_error(0.005).build();
+    assert!((default_error.scale.precision - PRECISION).abs() < 0.5);
+}
+
+#[test]
+#[cfg(not(feature = "saturating"))]
+#[should_panic(expected = "different bucketing")]
+fn merge_incompatible() {
+    let coarse = Histo::builder().precision(10.).build();
+    coarse.measure(100);
+    Histo::default().merge(&coarse);
+}
+
+#[test]
+#[cfg(not(feature = "saturating"))]
+#[should_panic(expected = "different bucketing")]
+fn add_incompatible() {
+    let _ = &Histo::default() + &Histo::signed();
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn parts() {
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i * 7);
+    }
+    let expected = c.clone();
+
+    let (vals, sum, count) = c.into_parts();
+    assert_eq!(vals.len(), BUCKETS);
+    assert_eq!(vals.iter().sum::<usize>(), 1000);
+    assert_eq!((sum, count), (expected.sum(), 1000));
+
+    let rebuilt = Histo::from_parts(vals, sum, count);
+    assert_eq!(rebuilt, expected);
+    assert_eq!(rebuilt.quantiles(&PERCENTILES), expected.quantiles(&PERCENTILES));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_midpoint() {
+    let c = Histo::builder().precision(2.).build();
+    assert!(c.percentile_midpoint(50.).is_nan());
+
+    let values: Vec<f64> = (0..10_000).map(|i| i as f64 + 0.5).collect();
+    for value in &values {
+        c.measure(*value);
+    }
+
+    let mut center_error = 0.;
+    let mut midpoint_error = 0.;
+    for p in 1..100 {
+        let truth = values[values.len() * p / 100];
+        let (low, high) = c.bucket_bounds(c.scale.compress(c.percentile(p as f64)));
+        let midpoint = c.percentile_midpoint(p as f64);
+        assert!(low < midpoint && midpoint < high);
+        assert!(c.percentile(p as f64) < midpoint);
+
+        center_error += (c.percentile(p as f64) - truth).abs();
+        midpoint_error += (midpoint - truth).abs();
+    }
+    assert!(midpoint_error < center_error);
+}
+
+#[test]
+fn for_each_bucket() {
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i);
+    }
+
+    let mut total = 0;
+    let mut last = f64::NEG_INFINITY;
+    c.for_each_bucket(|value, count| {
+        assert!(value > last);
+        last = value;
+        total += count;
+    });
+    assert_eq!(total as u64, c.count());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn geometric_mean() {
+    let untracked = Histo::default();
+    untracked.measure(2);
+    assert!(untracked.geometric_mean().is_nan());
+
+    let c = Histo::builder().track_geometric_mean(true).build();
+    assert!(c.geometric_mean().is_nan());
+
+    c.measure(2);
+    c.measure(8);
+    assert!((c.geometric_mean() - 4.).abs() < 1e-9);
+    assert_eq!(c.mean(), 5.);
+
+    c.measure(0);
+    c.measure(f64::INFINITY);
+    assert!((c.geometric_mean() - 4.).abs() < 1e-9);
+
+    let merged = Histo::builder().track_geometric_mean(true).build();
+    merged.merge(&c);
+    merged.measure_n(0.25, 2);
+    assert!((merged.geometric_mean() - 1.).abs() < 1e-9);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn extend() {
+    let expected = Histo::default();
+    let sources: Vec<Histo> = (0..3)
+        .map(|source| {
+            let h = Histo::default();
+            for i in 0..100 {
+                h.measure(source * 1000 + i);
+                expected.measure(source * 1000 + i);
+            }
+            h
+        })
+        .collect();
+
+    let mut by_ref = Histo::default();
+    by_ref.extend(&sources);
+    assert_eq!(by_ref, expected);
+
+    let mut owned = Histo::default();
+    owned.extend(sources);
+    assert_eq!(owned, expected);
+    assert_eq!(owned.quantiles(&PERCENTILES), expected.quantiles(&PERCENTILES));
+}
+
+#[test]
+#[cfg(all(feature = "std", not(feature = "disable")))]
+fn top_k() {
+    let untracked = Histo::default();
+    untracked.measure(5);
+    assert!(untracked.top_k().is_empty());
+
+    let c = Histo::builder().track_top_k(3).build();
+    for i in 0..10_000 {
+        c.measure(i % 100);
+    }
+    c.measure(123_456.7);
+    c.measure(98_765.4);
+    c.measure_n(5000.5, 2);
+    assert_eq!(c.top_k(), vec![123_456.7, 98_765.4, 5000.5]);
+
+    let merged = Histo::builder().track_top_k(3).build();
+    merged.measure(100_000);
+    merged.merge(&c);
+    assert_eq!(merged.top_k(), vec![123_456.7, 100_000., 98_765.4]);
+    assert_eq!(merged.clone().top_k(), merged.top_k());
+
+    c.reset();
+    assert!(c.top_k().is_empty());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn value_at_rank() {
+    let c = Histo::default();
+    assert!(c.value_at_rank(0).is_nan());
+
+    for i in 1..=10 {
+        c.measure_n(i * 100, 3);
+    }
+    let last = c.count() as usize - 1;
+
+    assert_eq!(c.value_at_rank(0), c.percentile(0.));
+    assert_eq!(c.value_at_rank(last), c.percentile(100.));
+    assert_eq!(c.value_at_rank(2), c.value_at_rank(0));
+    assert_eq!(c.value_at_rank(3), c.scale.decompress(c.scale.compress(200)));
+    assert!(c.value_at_rank(last + 1).is_nan());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+#[cfg_attr(
+    all(debug_assertions, not(feature = "saturating")),
+    should_panic(expected = "percentiles must be within")
+)]
+fn percentile_out_of_range() {
+    let c = Histo::default();
+    for i in 1..=100 {
+        c.measure(i);
+    }
+
+    assert_eq!(c.percentile(-1.), c.percentile(0.));
+    assert_eq!(c.percentile(101.), c.percentile(100.));
+    assert_eq!(c.quantiles(&[-5., 50., 150.]), c.quantiles(&[0., 50., 100.]));
+}
+
+#[test]
+#[cfg(all(feature = "std", not(feature = "disable")))]
+fn merge_all() {
+    let histos: Vec<Histo> = (0..32)
+        .map(|source| {
+            let h = Histo::builder().track_top_k(2).build();
+            for i in 0..1000 {
+                h.measure(source * 1000 + i);
+            }
+            h
+        })
+        .collect();
+
+    let sequential = Histo::builder().track_top_k(2).build();
+    for histo in &histos {
+        sequential.merge(histo);
+    }
+
+    let parallel = Histo::merge_all(&histos);
+    assert_eq!(parallel, sequential);
+    assert_eq!(parallel.min(), sequential.min());
+    assert_eq!(parallel.max(), sequential.max());
+    assert_eq!(parallel.top_k(), sequential.top_k());
+    assert_eq!(parallel.quantiles(&PERCENTILES), sequential.quantiles(&PERCENTILES));
+
+    for threads in &[1, 3, 16] {
+        let merged = Histo::merge_all_on(&histos, *threads);
+        assert_eq!(merged, sequential);
+        assert_eq!(merged.max(), sequential.max());
+    }
+
+    assert_eq!(Histo::merge_all(&histos[..2]), &histos[0] + &histos[1]);
+}
+
+// Compares `merge_all` against merging each histogram in turn. Run with
+// `cargo test --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+#[cfg(all(feature = "std", not(feature = "disable")))]
+fn merge_all_throughput() {
+    use std::time::Instant;
+
+    const HISTOS: u32 = 64;
+    const ROUNDS: u32 = 20;
+
+    let histos: Vec<Histo> = (0..HISTOS)
+        .map(|source| {
+            let h = Histo::default();
+            for i in 0..100_000 {
+                h.measure(source * 7 + i);
+            }
+            h
+        })
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let sequential = Histo::default();
+        for histo in &histos {
+            sequential.merge(histo);
+        }
+        assert_eq!(sequential.count(), 100_000 * HISTOS as u64);
+    }
+    let sequential = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let parallel = Histo::merge_all(&histos);
+        assert_eq!(parallel.count(), 100_000 * HISTOS as u64);
+    }
+    let parallel = start.elapsed();
+
+    std::println!(
+        "{} histograms, sequential: {:.2} ms/merge, merge_all ({} threads): {:.2} ms/merge",
+        HISTOS,
+        sequential.as_secs_f64() * 1000. / ROUNDS as f64,
+        std::thread::available_parallelism().map_or(1, |n| n.get()),
+        parallel.as_secs_f64() * 1000. / ROUNDS as f64
+    );
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn f32_results() {
+    let c = Histo::default();
+    assert!(c.percentile_f32(50.).is_nan() && c.mean_f32().is_nan());
+
+    for i in 0..10_000 {
+        c.measure(i as f64 * 1.37);
+    }
+    for p in &PERCENTILES {
+        let full = c.percentile(*p);
+        assert!((c.percentile_f32(*p) as f64 - full).abs() <= full * 1e-6);
+    }
+    assert!((c.mean_f32() as f64 - c.mean()).abs() <= c.mean() * 1e-6);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn observe_valid() {
+    let c = Histo::builder().valid_range(0., 10_000.).build();
+    for value in &[5., -1., 12., f64::NAN, 20_000., 0., 10_000., f64::INFINITY] {
+        c.observe_valid(*value);
+    }
+    assert_eq!(c.count(), 4);
+    assert_eq!(c.sum(), 10_017);
+    assert_eq!(c.min(), 0.);
+    assert_eq!(c.max(), 10_000.);
+
+    let unbounded = Histo::default();
+    unbounded.observe_valid(-1);
+    unbounded.observe_valid(f64::NAN);
+    assert_eq!(unbounded.count(), 1);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn diff_report() {
+    let baseline = Histo::default();
+    let candidate = Histo::default();
+    for _ in 0..100 {
+        baseline.measure(120);
+        candidate.measure(95);
+    }
+
+    // 120 is reported as the value of its bucket, 121
+    let report = candidate.diff_report(&baseline);
+    assert_eq!(report.lines().count(), PERCENTILES.len());
+    assert!(report.contains("p50: 121 -> 95 (-22%)\n"), "{}", report);
+    assert!(report.contains("p99.9: 121 -> 95 (-22%)\n"), "{}", report);
+
+    let unchanged = baseline.diff_report(&baseline);
+    assert!(unchanged.starts_with("p0: 121 -> 121 (+0%)\n"), "{}", unchanged);
+
+    let empty = candidate.diff_report(&Histo::default());
+    assert!(empty.starts_with("p0: NaN -> 95\n"), "{}", empty);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn display() {
+    let c = Histo::default();
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+
+    let table = format!("{}", c);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 2 + PERCENTILES.len());
+    assert_eq!(lines[0], " count  1000");
+    assert_eq!(lines[1], "  mean  500.50");
+    assert!(lines.contains(&"   p50  502"), "{}", table);
+    assert!(lines.contains(&"p99.99  1001"), "{}", table);
+}
+
+#[test]
+fn histogram_data() {
+    let c = Histo::default();
+    for i in 0..10_000 {
+        c.measure(1000 + i % 100);
+    }
+
+    let bars = c.histogram_data();
+    assert_eq!(bars.iter().map(|bar| bar.2).sum::<u64>(), c.count());
+    for pair in bars.windows(2) {
+        let ((low, high, _), (next_low, next_high, _)) = (pair[0], pair[1]);
+        assert!(low < high && next_low < next_high);
+        assert!((next_low - high).abs() <= high * 1e-12);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn bucket_count() {
+    let c = Histo::default();
+    c.measure_n(1000, 3);
+    c.measure(5);
+
+    let idx = compress(1000.);
+    assert_eq!(c.bucket_count(idx), 3);
+    assert_eq!(c.bucket_count(compress(5.)), 1);
+    assert_eq!(c.bucket_count(idx + 1), 0);
+
+    let small = Histo::builder().max_buckets(10).build();
+    assert_eq!(small.bucket_count(u16::MAX), 0);
+}
+
+#[test]
+#[cfg(not(any(
+    feature = "small_buckets",
+    feature = "u16_buckets",
+    feature = "disable"
+)))]
+fn percentile_target_precision() {
+    // with 2^54 + 1 observations, the median is the first one in the
+    // second bucket, which an f64 target of 2^53 would miss
+    let c = Histo::default();
+    c.measure_n(10, 1 << 53);
+    c.measure_n(1000, (1 << 53) + 1);
+
+    let high = c.scale.decompress(c.scale.compress(1000));
+    assert_eq!(c.median(), high);
+    assert_eq!(c.quantiles(&[50.]), vec![high]);
+    assert_eq!(c.snapshot().percentile(50.), high);
+    assert_eq!(c.percentile_count(50.), (1 << 54) + 1);
+
+    let exact = ((1u128 << 60) * 9_999).div_ceil(10_000) as u64;
+    assert_eq!(percentile_target(1 << 60, 99.99), exact);
+}
+
+#[test]
+#[cfg(all(feature = "saturating", not(feature = "disable")))]
+fn saturating() {
+    let c = Histo::default();
+    for i in 1..=100 {
+        c.measure(i);
+    }
+    c.measure(f64::INFINITY);
+    c.measure(f64::NAN);
+
+    assert_eq!(c.percentile(150.), c.percentile(100.));
+    assert_eq!(c.percentile(-5.), c.percentile(0.));
+    let (low, high) = c.percentile_range(90., 10.);
+    assert_eq!(low, c.percentile(90.));
+    assert_eq!(high, low);
+    assert_eq!(c.trimmed_mean(-10., 200.), c.trimmed_mean(0., 100.));
+
+    let before = c.count();
+    c.weighted_merge(&c.clone(), 1., -1.);
+    assert_eq!(c.count(), before);
+    c.decay(2.);
+    assert_eq!(c.count(), before);
+
+    // histograms with different bucketing are re-bucketed by value
+    let coarse = Histo::builder().precision(10.).build();
+    coarse.measure(1000);
+    let fine = Histo::default();
+    fine.merge(&coarse);
+    assert_eq!(fine.count(), 1);
+    assert_eq!(fine.sum(), 1000);
+    let value = coarse.percentile(50.);
+    assert_eq!(
+        fine.percentile(50.),
+        fine.scale.decompress(fine.scale.compress(value))
+    );
+    assert_eq!(fine.subtract(&coarse).count(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn from_iter() {
+    let expected = Histo::default();
+    for i in 0..1000 {
+        expected.measure(i as f64 * 1.5);
+    }
+
+    let c = Histo::from_iter((0..1000).map(|i| i as f64 * 1.5));
+    assert_eq!(c, expected);
+    assert_eq!(c.sum(), expected.sum());
+    assert_eq!(c.quantiles(&PERCENTILES), expected.quantiles(&PERCENTILES));
+
+    let collected: Histo = vec![1., 2., 3.].into_iter().collect();
+    assert_eq!(collected.count(), 3);
+    assert_eq!(
+        collected.percentile(50.),
+        expected.scale.decompress(expected.scale.compress(2.))
+    );
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn max_relative_error() {
+    let c = Histo::default();
+    assert!(c.max_relative_error().is_nan());
+    c.measure(0);
+    assert!(c.max_relative_error().is_nan());
+
+    for i in 1..=10_000 {
+        c.measure(i * 100);
+    }
+    let error = c.max_relative_error();
+    assert!(error > 0.004 && error < 0.01, "{}", error);
+
+    // small values are relatively further from the edges of their buckets
+    c.measure(1);
+    assert!(c.max_relative_error() > error);
+
+    let coarse = Histo::builder().precision(10.).build();
+    coarse.measure(1000);
+    assert!(coarse.max_relative_error() > 0.04);
+}
+
+#[test]
+fn try_build() {
+    assert!(Histo::builder().max_buckets(BUCKETS).try_build().is_ok());
+    assert_eq!(
+        Histo::builder().max_buckets(BUCKETS + 1).try_build().err(),
+        Some(BuildError::Buckets(BUCKETS + 1))
+    );
+    assert_eq!(
+        Histo::builder().max_buckets(0).try_build().err(),
+        Some(BuildError::Buckets(0))
+    );
+    assert!(Histo::builder().max_buckets(1).try_build().is_ok());
+    assert_eq!(
+        Histo::builder()
+            .max_buckets(1)
+            .signed(true)
+            .try_build()
+            .err(),
+        Some(BuildError::Buckets(1))
+    );
+    assert_eq!(
+        Histo::builder().precision(-1.).try_build().err(),
+        Some(BuildError::Precision(-1.))
+    );
+    assert!(matches!(
+        Histo::builder().precision(f64::NAN).try_build(),
+        Err(BuildError::Precision(_))
+    ));
+    for &base in &[0.5, 1., 0., -2., f64::INFINITY, f64::NAN] {
+        assert!(matches!(
+            Histo::builder().log_base(base).try_build(),
+            Err(BuildError::Precision(_))
+        ));
+    }
+    for &error in &[-0.5, 0., -1., -2., f64::INFINITY, f64::NAN] {
+        assert!(matches!(
+            Histo::builder().relative_error(error).try_build(),
+            Err(BuildError::Precision(_))
+        ));
+    }
+    assert!(Histo::builder().log_base(1.01).try_build().is_ok());
+    assert!(Histo::builder().relative_error(0.001).try_build().is_ok());
+    assert_eq!(
+        format!("{}", BuildError::Buckets(BUCKETS + 1)),
+        "max_buckets must be between 1 and 2^16, got 65537"
+    );
+
+    assert_eq!(
+        Histo::builder().valid_range(5., 1.).try_build().err(),
+        Some(BuildError::ValidRange(5., 1.))
+    );
+    assert!(matches!(
+        Histo::builder().valid_range(f64::NAN, 1.).try_build(),
+        Err(BuildError::ValidRange(..))
+    ));
+    assert!(Histo::builder().valid_range(1., 1.).try_build().is_ok());
+    #[cfg(feature = "std")]
+    assert_eq!(
+        Histo::builder().track_top_k(0).try_build().err(),
+        Some(BuildError::TopK(0))
+    );
+    #[cfg(feature = "reservoir")]
+    assert_eq!(
+        Histo::builder().reservoir(0).try_build().err(),
+        Some(BuildError::Reservoir(0))
+    );
+}
+
+#[test]
+#[should_panic(expected = "max_buckets must be between 1 and 2^16")]
+fn build_oversized() {
+    Histo::builder().max_buckets(BUCKETS + 1).build();
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn percentile_ceil() {
+    let c = Histo::default();
+    assert!(c.percentile_ceil(99.).is_nan());
+
+    for i in 0..10_000 {
+        c.measure(i as f64 * 1.37);
+    }
+
+    for p in 0..=1000 {
+        let p = p as f64 / 10.;
+        let ceil = c.percentile_ceil(p);
+        assert!(ceil >= c.percentile(p), "p{}", p);
+        assert!(ceil >= c.percentile_midpoint(p), "p{}", p);
+    }
+    assert!(c.percentile_ceil(100.) >= c.max());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn normalize() {
+    let c = Histo::default();
+    assert_eq!(c.normalize(1000).count(), 0);
+
+    for i in 1..=100 {
+        c.measure_n(i * 10, 100);
+    }
+    assert_eq!(c.count(), 10_000);
+
+    let ps = [0., 25., 50., 90., 99., 100.];
+    for &target in &[1000, 20_000] {
+        let normalized = c.normalize(target);
+        assert_eq!(normalized.count(), target as u64);
+        assert_eq!(normalized.quantiles(&ps), c.quantiles(&ps));
+        assert!((normalized.mean() - c.mean()).abs() < 1e-9);
+        assert_eq!(normalized.min(), c.min());
+        assert_eq!(normalized.max(), c.max());
+    }
+
+    let uneven = c.normalize(333);
+    assert!((uneven.count() as i64 - 333).abs() <= 50);
+    assert_eq!(uneven.percentile(50.), c.percentile(50.));
+}
+
+#[test]
+#[cfg(all(feature = "std", not(feature = "disable")))]
+fn write_percentiles() {
+    let c = Histo::default();
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+
+    let mut buf: Vec<u8> = vec![];
+    c.write_percentiles(&mut buf).unwrap();
+    let written = std::str::from_utf8(&buf).unwrap();
+    assert_eq!(written, format!("{:?}\n", c));
+    assert!(written.starts_with("Histogram[(0 -> 1) (50 -> 502) "));
+}
+
+#[test]
+#[cfg(feature = "disable")]
+fn disabled() {
+    let c = Histo::default();
+    assert_eq!(c.measure(10), 0);
+    assert_eq!(c.measure_n(10, 5), 0);
+    assert_eq!(c.measure_u64(10), 0);
+
+    let mut batch = c.batch();
+    batch.add(10);
+    assert_eq!(batch.count(), 0);
+    c.apply_batch(&mut batch);
+
+    assert_eq!(c.count(), 0);
+    assert!(c.percentile(50.).is_nan());
+    assert!(c.quantiles(&[0., 50., 100.]).iter().all(|p| p.is_nan()));
 }
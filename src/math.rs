@@ -0,0 +1,190 @@
+// Float routines that live in std rather than core. With the `std`
+// feature these forward to the inherent methods, otherwise they are
+// computed in software so the crate can be used under `no_std`.
+
+#[cfg(feature = "std")]
+mod imp {
+    pub(crate) fn ln(x: f64) -> f64 {
+        x.ln()
+    }
+
+    pub(crate) fn exp(x: f64) -> f64 {
+        x.exp()
+    }
+
+    pub(crate) fn trunc(x: f64) -> f64 {
+        x.trunc()
+    }
+
+    pub(crate) fn round(x: f64) -> f64 {
+        x.round()
+    }
+
+    pub(crate) fn ceil(x: f64) -> f64 {
+        x.ceil()
+    }
+
+    pub(crate) fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod imp {
+    use core::f64::consts::LN_2;
+
+    // 2^52, above which every f64 is already an integer
+    const INTEGRAL: f64 = 4_503_599_627_370_496.;
+
+    pub(crate) fn ln(x: f64) -> f64 {
+        if x.is_nan() || x < 0. {
+            return f64::NAN;
+        }
+        if x == 0. {
+            return f64::NEG_INFINITY;
+        }
+        if x.is_infinite() {
+            return x;
+        }
+
+        // split x into m * 2^e with m in [sqrt(1/2), sqrt(2))
+        let (mut m, mut e) = frexp(x);
+        if m < core::f64::consts::FRAC_1_SQRT_2 {
+            m *= 2.;
+            e -= 1;
+        }
+
+        // ln(m) = 2 * atanh((m - 1) / (m + 1))
+        let s = (m - 1.) / (m + 1.);
+        let s2 = s * s;
+        let mut term = s;
+        let mut sum = 0.;
+        let mut k = 1.;
+        while k < 40. {
+            sum += term / k;
+            term *= s2;
+            k += 2.;
+        }
+
+        2. * sum + e as f64 * LN_2
+    }
+
+    pub(crate) fn exp(x: f64) -> f64 {
+        if x.is_nan() {
+            return x;
+        }
+        if x > 709.8 {
+            return f64::INFINITY;
+        }
+        if x < -745.2 {
+            return 0.;
+        }
+
+        // exp(x) = 2^k * exp(r) with |r| <= ln(2) / 2
+        let k = round(x / LN_2);
+        let r = x - k * LN_2;
+
+        let mut term = 1.;
+        let mut sum = 1.;
+        let mut n = 1.;
+        while n < 20. {
+            term *= r / n;
+            sum += term;
+            n += 1.;
+        }
+
+        ldexp(sum, k as i32)
+    }
+
+    pub(crate) fn trunc(x: f64) -> f64 {
+        if x.is_nan() || x.abs() >= INTEGRAL {
+            return x;
+        }
+        (x as i64) as f64
+    }
+
+    pub(crate) fn round(x: f64) -> f64 {
+        let t = trunc(x);
+        if (x - t).abs() >= 0.5 {
+            t + x.signum()
+        } else {
+            t
+        }
+    }
+
+    pub(crate) fn ceil(x: f64) -> f64 {
+        let t = trunc(x);
+        if t < x {
+            t + 1.
+        } else {
+            t
+        }
+    }
+
+    pub(crate) fn sqrt(x: f64) -> f64 {
+        if x.is_nan() || x < 0. {
+            return f64::NAN;
+        }
+        if x == 0. || x.is_infinite() {
+            return x;
+        }
+
+        // halve the exponent for a starting guess, then refine
+        let (m, e) = frexp(x);
+        let mut y = ldexp(m, e / 2);
+        for _ in 0..8 {
+            y = 0.5 * (y + x / y);
+        }
+        y
+    }
+
+    // splits a finite, positive x into m * 2^e with m in [1/2, 1)
+    fn frexp(x: f64) -> (f64, i32) {
+        let mut x = x;
+        let mut e = 0;
+        // bring subnormals into the normal range
+        if x < f64::MIN_POSITIVE {
+            x *= 18_014_398_509_481_984.; // 2^54
+            e -= 54;
+        }
+        let bits = x.to_bits();
+        let biased = ((bits >> 52) & 0x7ff) as i32;
+        let m = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+        (m, e + biased - 1022)
+    }
+
+    // computes m * 2^e without overflowing intermediate steps
+    fn ldexp(m: f64, e: i32) -> f64 {
+        let mut m = m;
+        let mut e = e;
+        while e > 1023 {
+            m *= f64::from_bits(2046 << 52);
+            e -= 1023;
+        }
+        while e < -1022 {
+            m *= f64::from_bits(1 << 52);
+            e += 1022;
+        }
+        m * f64::from_bits(((e + 1023) as u64) << 52)
+    }
+}
+
+pub(crate) use imp::*;
+
+#[test]
+fn math() {
+    let close = |a: f64, b: f64| (a - b).abs() <= b.abs() * 1e-12;
+
+    for &x in &[1e-300, 0.001, 0.5, 1., 2., 10., 12345.678, 1e300] {
+        assert!(close(ln(x), x.ln()), "ln({})", x);
+        assert!(close(sqrt(x), x.sqrt()), "sqrt({})", x);
+    }
+    for &x in &[-700., -1., 0., 0.5, 1., 10., 700.] {
+        assert!(close(exp(x), x.exp()), "exp({})", x);
+    }
+    for &x in &[-2.5, -0.5, -0.4, 0.4, 0.5, 1.5, 2.49, 1e20] {
+        assert_eq!(round(x), x.round(), "round({})", x);
+        assert_eq!(trunc(x), x.trunc(), "trunc({})", x);
+        assert_eq!(ceil(x), x.ceil(), "ceil({})", x);
+    }
+}
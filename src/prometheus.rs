@@ -0,0 +1,128 @@
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+
+use super::Histo;
+
+impl Histo {
+    /// Render this histogram in the Prometheus text exposition format,
+    /// with one cumulative `_bucket` line per nonzero bucket. The `le`
+    /// label of each line is the upper bound of its bucket.
+    pub fn to_prometheus(&self, name: &str) -> String {
+        let mut ret = String::new();
+        writeln!(ret, "# TYPE {} histogram", name).unwrap();
+
+        let mut cumulative = 0;
+        for (idx, val) in self.vals.iter().enumerate().take(self.vals.len() - 1) {
+            let count = val.load(Ordering::Acquire);
+            if count == 0 {
+                continue;
+            }
+
+            cumulative += count;
+            let (_, le) = self.scale.bounds(idx as u16);
+            writeln!(ret, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).unwrap();
+        }
+
+        let count = self.count();
+        writeln!(ret, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        writeln!(ret, "{}_sum {}", name, self.sum_f64()).unwrap();
+        writeln!(ret, "{}_count {}", name, count).unwrap();
+
+        ret
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn to_prometheus() {
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i % 100);
+    }
+
+    let text = c.to_prometheus("latency");
+    assert!(text.starts_with("# TYPE latency histogram\n"));
+
+    let mut last_cumulative = 0;
+    let mut last_le = f64::NEG_INFINITY;
+    let mut inf_bucket = None;
+    let mut sum = None;
+    let mut count = None;
+
+    for line in text.lines().skip(1) {
+        let (key, value) = line.split_at(line.rfind(' ').unwrap());
+        if key == "latency_sum" {
+            sum = Some(value.trim().parse::<f64>().unwrap());
+            continue;
+        }
+        let value: u64 = value.trim().parse().unwrap();
+
+        if key == "latency_bucket{le=\"+Inf\"}" {
+            inf_bucket = Some(value);
+        } else if key.starts_with("latency_bucket{le=\"") {
+            let le: f64 = key["latency_bucket{le=\"".len()..key.len() - 2]
+                .parse()
+                .unwrap();
+            assert!(le > last_le);
+            assert!(value > last_cumulative);
+            last_le = le;
+            last_cumulative = value;
+        } else if key == "latency_count" {
+            count = Some(value);
+        } else {
+            panic!("unexpected line {}", line);
+        }
+    }
+
+    assert_eq!(last_cumulative, c.count());
+    assert_eq!(inf_bucket, Some(c.count()));
+    assert_eq!(sum, Some(c.sum_f64()));
+    assert_eq!(count, Some(c.count()));
+
+    // the sum of a signed histogram may be negative
+    let c = Histo::signed();
+    c.measure(-10);
+    c.measure(5);
+    assert!(c.to_prometheus("delta").contains("\ndelta_sum -5\n"));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn prometheus_le() {
+    use alloc::vec::Vec;
+
+    let c = Histo::default();
+    let values: Vec<f64> = (0..2000)
+        .map(|i| i as f64 * 1.37 + 0.3)
+        .chain(Some(101.258))
+        .collect();
+    for value in &values {
+        c.measure(*value);
+    }
+
+    let les: Vec<f64> = c
+        .to_prometheus("latency")
+        .lines()
+        .filter_map(|line| line.strip_prefix("latency_bucket{le=\""))
+        .filter_map(|rest| rest.split('"').next()?.parse().ok())
+        .filter(|le: &f64| le.is_finite())
+        .collect();
+    let populated: Vec<u16> = (0..c.vals.len())
+        .filter(|idx| c.vals[*idx].load(Ordering::Relaxed) != 0)
+        .map(|idx| idx as u16)
+        .collect();
+    assert_eq!(les.len(), populated.len());
+
+    // each value is at or below the le of its own bucket, and above the
+    // le of the previous one, whose cumulative count excludes it
+    for value in &values {
+        let idx = c.scale.compress(*value);
+        let position = populated.binary_search(&idx).unwrap();
+        assert!(*value <= les[position], "{} is above le={}", value, les[position]);
+        if position > 0 {
+            let previous = les[position - 1];
+            assert!(*value > previous, "{} is within le={}", value, previous);
+        }
+    }
+}
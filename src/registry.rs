@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+
+use super::Histo;
+
+static REGISTRY: OnceLock<Mutex<BTreeMap<String, &'static Histo>>> = OnceLock::new();
+
+/// Returns a reference to a `Histo` with the given name, shared by the
+/// whole process. It is created on first use and never deallocated.
+/// The `histogram!` macro is a shorthand for this.
+pub fn registered(name: &str) -> &'static Histo {
+    let mut registry = REGISTRY
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    if let Some(histo) = registry.get(name) {
+        return histo;
+    }
+
+    let histo: &'static Histo = Box::leak(Box::default());
+    registry.insert(name.to_owned(), histo);
+    histo
+}
+
+/// Print the percentiles of every histogram created by `registered` or
+/// `histogram!`, in order of their names.
+pub fn report_all() {
+    let registry = match REGISTRY.get() {
+        Some(registry) => registry.lock().unwrap_or_else(|e| e.into_inner()),
+        None => return,
+    };
+
+    for (name, histo) in registry.iter() {
+        println!("{}: {:?}", name, histo);
+    }
+}
+
+/// Returns a process-global `Histo` with the given name, creating it on
+/// first use. Every call site using the same name shares one histogram.
+///
+/// ```
+/// historian::histogram!("db_latency").measure(12);
+/// # #[cfg(not(feature = "disable"))]
+/// assert_eq!(historian::histogram!("db_latency").count(), 1);
+/// ```
+#[macro_export]
+macro_rules! histogram {
+    ($name:expr) => {
+        $crate::registered($name)
+    };
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn registry() {
+    fn first_call_site() {
+        histogram!("registry_test").measure(10);
+    }
+
+    fn second_call_site() {
+        histogram!("registry_test").measure(20);
+    }
+
+    first_call_site();
+    second_call_site();
+
+    let histo = histogram!("registry_test");
+    assert_eq!(histo.count(), 2);
+    assert_eq!(histo.sum(), 30);
+    assert!(std::ptr::eq(histo, registered("registry_test")));
+    assert_eq!(registered("registry_other").count(), 0);
+
+    report_all();
+}
@@ -0,0 +1,73 @@
+use super::{Histo, PERCENTILES};
+
+/// The percentiles printed by `Histo::print_percentiles`, as returned by
+/// `Histo::report` for consumption by other serializers. Each field is
+/// NAN if no metrics had been collected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    /// The minimum, or 0th percentile.
+    pub p0: f64,
+    /// The median, or 50th percentile.
+    pub p50: f64,
+    /// The 75th percentile.
+    pub p75: f64,
+    /// The 90th percentile.
+    pub p90: f64,
+    /// The 95th percentile.
+    pub p95: f64,
+    /// The 97.5th percentile.
+    pub p97_5: f64,
+    /// The 99th percentile.
+    pub p99: f64,
+    /// The 99.9th percentile.
+    pub p999: f64,
+    /// The 99.99th percentile.
+    pub p9999: f64,
+    /// The maximum, or 100th percentile.
+    pub p100: f64,
+}
+
+impl Histo {
+    /// Retrieve the standard set of percentiles as structured data,
+    /// using a single scan over the buckets.
+    pub fn report(&self) -> Percentiles {
+        let results = self.quantiles(&PERCENTILES);
+
+        Percentiles {
+            p0: results[0],
+            p50: results[1],
+            p75: results[2],
+            p90: results[3],
+            p95: results[4],
+            p97_5: results[5],
+            p99: results[6],
+            p999: results[7],
+            p9999: results[8],
+            p100: results[9],
+        }
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn report() {
+    let c = Histo::default();
+    let empty = c.report();
+    assert!(empty.p0.is_nan() && empty.p100.is_nan());
+
+    for i in 1..=10_000 {
+        c.measure(i);
+    }
+
+    let report = c.report();
+    assert_eq!(report.p0, c.percentile(0.));
+    assert_eq!(report.p50, c.percentile(50.));
+    assert_eq!(report.p75, c.percentile(75.));
+    assert_eq!(report.p90, c.percentile(90.));
+    assert_eq!(report.p95, c.percentile(95.));
+    assert_eq!(report.p97_5, c.percentile(97.5));
+    assert_eq!(report.p99, c.percentile(99.));
+    assert_eq!(report.p999, c.percentile(99.9));
+    assert_eq!(report.p9999, c.percentile(99.99));
+    assert_eq!(report.p100, c.percentile(100.));
+}
@@ -0,0 +1,84 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::{Histo, Percentiles};
+
+/// A handle to a background thread started by `spawn_reporter`, which
+/// stops the thread when dropped.
+pub struct Reporter {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        // disconnecting the channel wakes the thread immediately
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Spawn a thread that calls `emit` with `name` and the `Histo::report`
+/// of `histo` once every `interval`, until the returned `Reporter` is
+/// dropped. `emit` can forward the percentiles to a logging or tracing
+/// framework as a structured event.
+pub fn spawn_reporter<F>(histo: Arc<Histo>, interval: Duration, name: &str, mut emit: F) -> Reporter
+where
+    F: FnMut(&str, &Percentiles) + Send + 'static,
+{
+    let (stop, stopped) = mpsc::channel::<()>();
+    let name = name.to_owned();
+
+    let thread = thread::spawn(move || loop {
+        match stopped.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => emit(&name, &histo.report()),
+            _ => return,
+        }
+    });
+
+    Reporter {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn reporter() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    let histo = Arc::new(Histo::default());
+    histo.measure(100);
+    let events = Arc::new(AtomicUsize::new(0));
+
+    let reporter = {
+        let events = events.clone();
+        let expected = histo.report();
+        spawn_reporter(
+            histo.clone(),
+            Duration::from_millis(5),
+            "latency",
+            move |name, report| {
+                assert_eq!(name, "latency");
+                assert_eq!(*report, expected);
+                events.fetch_add(1, Ordering::SeqCst);
+            },
+        )
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while events.load(Ordering::SeqCst) == 0 {
+        assert!(Instant::now() < deadline, "no report was emitted");
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    drop(reporter);
+    let emitted = events.load(Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(events.load(Ordering::SeqCst), emitted);
+}
@@ -0,0 +1,189 @@
+use std::sync::Mutex;
+
+use super::{math, Histo};
+
+// Reservoir retains a uniform random sample of at most `capacity` raw
+// values measured into a `Histo`, using Algorithm L. It is a validation
+// aid rather than a fast path, so every insertion takes a lock.
+#[derive(Debug)]
+pub(crate) struct Reservoir {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    samples: Vec<f64>,
+    // the number of values offered to the reservoir
+    seen: u64,
+    // the 1-based position of the next offered value to be sampled once
+    // the reservoir is full, and the weight used to draw the skip after it
+    next: u64,
+    weight: f64,
+    // xorshift64 state, which must never be zero
+    rng: u64,
+}
+
+impl State {
+    fn new(capacity: usize) -> State {
+        State {
+            samples: Vec::with_capacity(capacity),
+            seen: 0,
+            next: u64::MAX,
+            weight: 1.,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    // returns a uniform random value in (0, 1)
+    fn next_unit(&mut self) -> f64 {
+        ((self.next_random() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    // draws the position of the next value to be sampled
+    fn skip(&mut self, capacity: usize) {
+        self.weight *= math::exp(math::ln(self.next_unit()) / capacity as f64);
+        let skip = math::ln(self.next_unit()) / math::ln(1. - self.weight);
+        self.next = self.next.saturating_add(skip as u64).saturating_add(1);
+    }
+}
+
+impl Clone for Reservoir {
+    fn clone(&self) -> Reservoir {
+        Reservoir {
+            capacity: self.capacity,
+            state: Mutex::new(self.state.lock().unwrap_or_else(|e| e.into_inner()).clone()),
+        }
+    }
+}
+
+impl Reservoir {
+    pub(crate) fn new(capacity: usize) -> Reservoir {
+        Reservoir {
+            capacity,
+            state: Mutex::new(State::new(capacity)),
+        }
+    }
+
+    // offers `n` occurrences of `value`, in time proportional to the
+    // number of them that end up sampled rather than to `n`
+    #[cfg_attr(feature = "disable", allow(dead_code))]
+    pub(crate) fn insert(&self, value: f64, n: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut n = n as u64;
+
+        while n > 0 && state.samples.len() < self.capacity {
+            state.samples.push(value);
+            state.seen += 1;
+            n -= 1;
+            if state.samples.len() == self.capacity {
+                state.next = state.seen;
+                state.skip(self.capacity);
+            }
+        }
+
+        let last = state.seen.saturating_add(n);
+        while state.next <= last {
+            let slot = state.next_random() % self.capacity as u64;
+            state.samples[slot as usize] = value;
+            state.skip(self.capacity);
+        }
+        state.seen = last;
+    }
+
+    // returns the retained values in ascending order
+    pub(crate) fn sorted(&self) -> Vec<f64> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut samples = state.samples.clone();
+        samples.sort_by(|a, b| a.total_cmp(b));
+        samples
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = State::new(self.capacity);
+    }
+}
+
+impl Histo {
+    /// Retrieve a percentile [0-100] of the raw values retained by the
+    /// reservoir sample configured with `HistoBuilder::reservoir`, by
+    /// sorting them and taking the nearest rank. While the sample holds
+    /// every value measured this is the exact percentile, which can be
+    /// used to validate the bucketed results of `percentile`. Returns
+    /// NAN if there is no reservoir or no metrics have been collected.
+    pub fn exact_percentile(&self, p: f64) -> f64 {
+        let samples = match &self.reservoir {
+            Some(reservoir) => reservoir.sorted(),
+            None => return f64::NAN,
+        };
+        if samples.is_empty() {
+            return f64::NAN;
+        }
+
+        let p = super::clamp_percentile(p);
+        let rank = super::math::ceil(p / 100. * samples.len() as f64) as usize;
+        samples[rank.max(1).min(samples.len()) - 1]
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn reservoir() {
+    let c = Histo::builder().reservoir(100_000).build();
+    assert!(c.exact_percentile(50.).is_nan());
+    assert!(Histo::default().exact_percentile(50.).is_nan());
+
+    for i in 1..=100_000 {
+        c.measure(i);
+    }
+    assert_eq!(c.exact_percentile(0.), 1.);
+    assert_eq!(c.exact_percentile(50.), 50_000.);
+    assert_eq!(c.exact_percentile(100.), 100_000.);
+
+    // the bucketed percentiles are within about 0.5% of the truth
+    for p in 1..=100 {
+        let exact = c.exact_percentile(p as f64);
+        let bucketed = c.percentile(p as f64);
+        assert!((bucketed - exact).abs() / exact < 0.006, "p{}", p);
+    }
+
+    // a smaller reservoir holds a bounded sample of the measured values
+    let sampled = Histo::builder().reservoir(1000).build();
+    for i in 1..=100_000 {
+        sampled.measure(i);
+    }
+    let samples = sampled.reservoir.as_ref().unwrap().sorted();
+    assert_eq!(samples.len(), 1000);
+    assert!(samples.iter().all(|v| (1. ..=100_000.).contains(v)));
+    let median = sampled.exact_percentile(50.);
+    assert!((median - 50_000.).abs() < 5_000., "{}", median);
+
+    sampled.reset();
+    assert!(sampled.exact_percentile(50.).is_nan());
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn reservoir_measure_n() {
+    let c = Histo::builder().reservoir(1000).build();
+    c.measure_n(1., 1 << 20);
+    c.measure_n(2., 1 << 20);
+    let samples = c.reservoir.as_ref().unwrap().sorted();
+    assert_eq!(samples.len(), 1000);
+    let twos = samples.iter().filter(|v| **v == 2.).count();
+    assert!((400..600).contains(&twos), "{}", twos);
+
+    // a huge batch of copies takes over the sample without a copy each
+    c.measure_n(3., 1 << 40);
+    assert_eq!(c.exact_percentile(50.), 3.);
+    c.measure(4.);
+    assert_eq!(c.reservoir.as_ref().unwrap().sorted().len(), 1000);
+}
@@ -0,0 +1,84 @@
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+// the number of failed reads after which a reader holds off new writers
+const PATIENCE: usize = 4;
+
+// SeqLock lets a reader detect whether the observations of a `Histo`
+// changed while it was reading them. Unlike a classic seqlock there are
+// many concurrent writers, so each announces itself in `writers` and
+// bumps `generation` once it is done. A read is torn-free if no writer
+// was active when it began and nothing changed by the time it ended.
+// Readers that keep failing set `paused`, which new writers wait out,
+// so that a snapshot eventually succeeds under constant measurement.
+#[derive(Debug, Default)]
+pub(crate) struct SeqLock {
+    writers: AtomicUsize,
+    generation: AtomicUsize,
+    paused: AtomicUsize,
+}
+
+pub(crate) struct WriteGuard<'a>(&'a SeqLock);
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        self.0.generation.fetch_add(1, Ordering::Release);
+        self.0.writers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+// waits briefly for another thread, yielding to it if possible since
+// it may be descheduled in the middle of a write
+#[inline]
+fn relax() {
+    #[cfg(feature = "std")]
+    std::thread::yield_now();
+    #[cfg(not(feature = "std"))]
+    core::hint::spin_loop();
+}
+
+impl SeqLock {
+    // marks the start of a write, which ends when the guard is dropped
+    #[inline]
+    pub(crate) fn write(&self) -> WriteGuard<'_> {
+        while self.paused.load(Ordering::Acquire) != 0 {
+            relax();
+        }
+        self.writers.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        WriteGuard(self)
+    }
+
+    // calls `f` until it runs without overlapping any write
+    pub(crate) fn read<T, F: FnMut() -> T>(&self, mut f: F) -> T {
+        let mut attempts = 0;
+        let mut paused = false;
+
+        let ret = loop {
+            if attempts == PATIENCE && !paused {
+                self.paused.fetch_add(1, Ordering::AcqRel);
+                paused = true;
+            }
+            attempts += 1;
+
+            let generation = self.generation.load(Ordering::Acquire);
+            if self.writers.load(Ordering::Acquire) != 0 {
+                relax();
+                continue;
+            }
+
+            let ret = f();
+
+            fence(Ordering::Acquire);
+            if self.writers.load(Ordering::Acquire) == 0
+                && self.generation.load(Ordering::Relaxed) == generation
+            {
+                break ret;
+            }
+        };
+
+        if paused {
+            self.paused.fetch_sub(1, Ordering::Release);
+        }
+        ret
+    }
+}
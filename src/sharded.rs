@@ -0,0 +1,154 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::thread;
+
+use super::Histo;
+
+thread_local! {
+    static THREAD_HASH: usize = {
+        let mut hasher = DefaultHasher::new();
+        thread::current().id().hash(&mut hasher);
+        hasher.finish() as usize
+    };
+}
+
+/// A collector that spreads measurements across several inner `Histo`s
+/// based on the measuring thread, reducing contention between threads
+/// that measure concurrently. Reads merge every shard, so they cost
+/// proportionally more than reading a single `Histo`.
+pub struct ShardedHisto {
+    shards: Vec<Histo>,
+}
+
+impl Default for ShardedHisto {
+    /// Create a `ShardedHisto` with one shard per available CPU.
+    fn default() -> ShardedHisto {
+        let shards = thread::available_parallelism().map_or(1, |n| n.get());
+        ShardedHisto::new(shards)
+    }
+}
+
+impl ShardedHisto {
+    /// Create a `ShardedHisto` with the given number of shards.
+    pub fn new(shards: usize) -> ShardedHisto {
+        assert!(shards > 0, "a ShardedHisto requires at least one shard");
+
+        ShardedHisto {
+            shards: (0..shards).map(|_| Histo::default()).collect(),
+        }
+    }
+
+    /// Record a value into the shard for the current thread.
+    #[inline]
+    pub fn measure<T: Into<f64>>(&self, raw_value: T) -> usize {
+        let idx = THREAD_HASH.with(|hash| hash % self.shards.len());
+        self.shards[idx].measure(raw_value)
+    }
+
+    /// Merge every shard into a single `Histo`.
+    pub fn merged(&self) -> Histo {
+        let ret = Histo::default();
+        for shard in &self.shards {
+            ret.merge(shard);
+        }
+        ret
+    }
+
+    /// Retrieve a percentile [0-100] across all shards. Returns NAN if
+    /// no metrics have been collected yet.
+    pub fn percentile(&self, p: f64) -> f64 {
+        self.merged().percentile(p)
+    }
+
+    /// Return the sum of all observations across all shards.
+    pub fn sum(&self) -> u64 {
+        self.shards.iter().map(Histo::sum).sum()
+    }
+
+    /// Return the count of observations across all shards.
+    pub fn count(&self) -> u64 {
+        self.shards.iter().map(Histo::count).sum()
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn sharded() {
+    use std::sync::Arc;
+
+    let sharded = Arc::new(ShardedHisto::new(4));
+    let baseline = Histo::default();
+    let mut threads = vec![];
+
+    for t in 0..8 {
+        for i in 0..1000 {
+            baseline.measure(t * 100 + i);
+        }
+
+        let sharded = sharded.clone();
+        threads.push(thread::spawn(move || {
+            for i in 0..1000 {
+                sharded.measure(t * 100 + i);
+            }
+        }));
+    }
+
+    for t in threads.into_iter() {
+        t.join().unwrap();
+    }
+
+    assert_eq!(sharded.count(), baseline.count());
+    assert_eq!(sharded.sum(), baseline.sum());
+    assert_eq!(sharded.merged(), baseline);
+    for p in &[0., 50., 90., 99., 100.] {
+        assert_eq!(sharded.percentile(*p), baseline.percentile(*p));
+    }
+}
+
+// Compares measuring from several threads into one `Histo` against
+// measuring into a `ShardedHisto`. Run with
+// `cargo test --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+#[cfg(not(feature = "disable"))]
+fn sharded_throughput() {
+    use std::time::{Duration, Instant};
+
+    const THREADS: u32 = 8;
+    const N: u32 = 2_000_000;
+
+    fn run<F: Fn(u32) + Sync>(measure: F) -> Duration {
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for i in 0..N {
+                        measure(i % 10_000);
+                    }
+                });
+            }
+        });
+        start.elapsed()
+    }
+
+    let h = Histo::default();
+    let single = run(|v| {
+        h.measure(v);
+    });
+
+    let sharded = ShardedHisto::default();
+    let spread = run(|v| {
+        sharded.measure(v);
+    });
+
+    let total = (THREADS * N) as f64;
+    std::println!(
+        "{} threads, single: {:.1} ns/measure, sharded ({} shards): {:.1} ns/measure",
+        THREADS,
+        single.as_nanos() as f64 / total,
+        sharded.shards.len(),
+        spread.as_nanos() as f64 / total
+    );
+    assert_eq!(h.count(), (THREADS * N) as u64);
+    assert_eq!(sharded.count(), (THREADS * N) as u64);
+}
@@ -0,0 +1,273 @@
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::Ordering;
+
+use super::{clamp_percentile, percentile_target, rounded_sum, Histo, Scale};
+
+/// A frozen, compact copy of the nonzero buckets of a `Histo`, which
+/// answers percentile queries with a binary search rather than a scan
+/// over every bucket.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    // (bucket, cumulative count up to and including the bucket)
+    cumulative: Vec<(u16, usize)>,
+    sum: u64,
+    scale: Scale,
+}
+
+impl Snapshot {
+    pub(crate) fn new(histo: &Histo) -> Snapshot {
+        let read = || {
+            Snapshot::from_counts(
+                histo.vals.iter().map(|val| val.load(Ordering::Acquire)),
+                histo.sum(),
+                histo.scale,
+            )
+        };
+
+        match &histo.seqlock {
+            Some(seqlock) => seqlock.read(read),
+            None => read(),
+        }
+    }
+
+    // Atomically takes every observation out of `histo`.
+    pub(crate) fn drain(histo: &Histo) -> Snapshot {
+        let _guard = histo.write_guard();
+
+        let counts: Vec<usize> = histo
+            .vals
+            .iter()
+            .map(|val| val.swap(0, Ordering::AcqRel))
+            .collect();
+
+        let sum = rounded_sum(
+            histo.sum.swap(0, Ordering::AcqRel),
+            f64::from_bits(histo.residual.swap(0, Ordering::AcqRel)),
+        );
+        histo.sum_sq.swap(0, Ordering::AcqRel);
+        histo.sq_count.swap(0, Ordering::AcqRel);
+        histo.overflows.swap(0, Ordering::AcqRel);
+        histo.clamped.swap(0, Ordering::AcqRel);
+        histo.log_sum.swap(0, Ordering::AcqRel);
+        histo.log_count.swap(0, Ordering::AcqRel);
+        histo.count.swap(0, Ordering::AcqRel);
+        histo.min.swap(f64::INFINITY.to_bits(), Ordering::AcqRel);
+        histo
+            .max
+            .swap(f64::NEG_INFINITY.to_bits(), Ordering::AcqRel);
+        #[cfg(feature = "std")]
+        {
+            if let Some(top) = &histo.top {
+                top.clear();
+            }
+        }
+        #[cfg(feature = "reservoir")]
+        {
+            if let Some(reservoir) = &histo.reservoir {
+                reservoir.clear();
+            }
+        }
+
+        Snapshot::from_counts(counts, sum, histo.scale)
+    }
+
+    fn from_counts<I: IntoIterator<Item = usize>>(counts: I, sum: u64, scale: Scale) -> Snapshot {
+        let mut cumulative = vec![];
+        let mut total = 0;
+
+        for (idx, count) in counts.into_iter().enumerate() {
+            if count != 0 {
+                total += count;
+                cumulative.push((idx as u16, total));
+            }
+        }
+
+        Snapshot {
+            cumulative,
+            sum,
+            scale,
+        }
+    }
+
+    /// Return the sum of all observations in this snapshot.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Return the count of observations in this snapshot.
+    pub fn count(&self) -> u64 {
+        self.cumulative.last().map_or(0, |&(_, sum)| sum as u64)
+    }
+
+    /// Retrieve a percentile [0-100]. Returns NAN if the snapshot
+    /// contains no observations.
+    pub fn percentile(&self, p: f64) -> f64 {
+        let p = clamp_percentile(p);
+
+        let count = self.count();
+
+        if count == 0 {
+            return f64::NAN;
+        }
+
+        let target = percentile_target(count, p);
+        let idx = self
+            .cumulative
+            .partition_point(|&(_, sum)| (sum as u64) < target)
+            .min(self.cumulative.len() - 1);
+
+        self.scale.decompress(self.cumulative[idx].0)
+    }
+
+    /// Retrieve several percentiles [0-100], returned in the order
+    /// requested.
+    pub fn quantiles(&self, ps: &[f64]) -> Vec<f64> {
+        ps.iter().map(|p| self.percentile(*p)).collect()
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn snapshot() {
+    let c = Histo::default();
+    assert!(c.snapshot().percentile(50.).is_nan());
+
+    for i in 0..10_000 {
+        c.measure((i * 7) % 3001);
+    }
+
+    let snapshot = c.snapshot();
+    assert_eq!(snapshot.count(), c.count());
+
+    let ps: Vec<f64> = (0..=1000).map(|p| p as f64 / 10.).collect();
+    for p in &ps {
+        assert_eq!(snapshot.percentile(*p), c.percentile(*p));
+    }
+    assert_eq!(snapshot.quantiles(&ps), c.quantiles(&ps));
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn consistent_snapshot() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    let c = Arc::new(Histo::builder().consistent_snapshots(true).build());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let c = c.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let batch = Histo::default();
+                batch.measure_n(7, 10);
+                // bounded so that no bucket overflows with u16_buckets
+                for _ in 0..1000 {
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    match writer {
+                        0 => c.merge(&batch),
+                        1 => {
+                            let mut local = c.local();
+                            for _ in 0..3 {
+                                local.measure(7);
+                            }
+                        }
+                        _ => {
+                            c.measure(7);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for _ in 0..100 {
+        let snapshot = c.snapshot();
+        assert_eq!(snapshot.sum(), 7 * snapshot.count());
+    }
+
+    done.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let snapshot = c.snapshot();
+    assert_eq!(snapshot.count(), c.count());
+    assert_eq!(snapshot.sum(), c.sum());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn consistent_drain() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+
+    let c = Arc::new(Histo::builder().consistent_snapshots(true).build());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let writer = {
+        let c = c.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut drained = 0;
+            for _ in 0..1000 {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                c.measure_n(7, 3);
+                drained += c.drain().count();
+            }
+            drained
+        })
+    };
+
+    for _ in 0..100 {
+        let snapshot = c.snapshot();
+        assert_eq!(snapshot.sum(), 7 * snapshot.count());
+    }
+
+    done.store(true, Ordering::Relaxed);
+    let drained = writer.join().unwrap();
+    assert_eq!(drained % 3, 0);
+    assert_eq!(c.count(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn drain() {
+    use super::bucket::Bucket;
+
+    let c = Histo::default();
+    for i in 0..1000 {
+        c.measure(i);
+    }
+    let expected = c.quantiles(&[0., 50., 99., 100.]);
+
+    let idx = c.scale.compress(0) as usize;
+    let full = c.vals[idx].swap(Bucket::MAX, Ordering::Relaxed);
+    c.measure(0);
+    c.vals[idx].store(full, Ordering::Relaxed);
+    assert_eq!(c.overflow_count(), 1);
+
+    let drained = c.drain();
+    assert_eq!(drained.count(), 1000);
+    assert_eq!(drained.sum(), 499_500);
+    assert_eq!(drained.quantiles(&[0., 50., 99., 100.]), expected);
+
+    assert_eq!(c.count(), 0);
+    assert_eq!(c.sum(), 0);
+    assert_eq!(c.overflow_count(), 0);
+    assert!(c.min().is_nan());
+    assert_eq!(c.buckets().count(), 0);
+
+    c.measure(7);
+    c.measure(f64::INFINITY);
+    assert_eq!(c.clamped_count(), 1);
+    assert_eq!(c.drain().count(), 2);
+    assert_eq!(c.clamped_count(), 0);
+}
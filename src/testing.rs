@@ -0,0 +1,43 @@
+use super::Histo;
+
+impl Histo {
+    /// Assert that a percentile [0-100] is within `tolerance_pct` percent
+    /// of `expected`, panicking with a descriptive message otherwise.
+    pub fn assert_percentile_near(&self, p: f64, expected: f64, tolerance_pct: f64) {
+        let actual = self.percentile(p);
+        let tolerance = expected.abs() * tolerance_pct / 100.;
+
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected p{} to be within {}% of {}, but it was {} ({} observations)",
+            p,
+            tolerance_pct,
+            expected,
+            actual,
+            self.count(),
+        );
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn assert_percentile_near() {
+    let c = Histo::default();
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+
+    c.assert_percentile_near(50., 500., 1.);
+    c.assert_percentile_near(99., 990., 1.);
+}
+
+#[test]
+#[should_panic(expected = "expected p50 to be within 1% of 400")]
+fn assert_percentile_near_fails() {
+    let c = Histo::default();
+    for i in 1..=1000 {
+        c.measure(i);
+    }
+
+    c.assert_percentile_near(50., 400., 1.);
+}
@@ -0,0 +1,136 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use super::Histo;
+
+/// A source of timestamps for a `Timer`. The default `MonotonicClock`
+/// reads `Instant`, and tests can substitute a synthetic clock to record
+/// exact, deterministic durations.
+pub trait Clock {
+    /// Return the current time in nanoseconds since an arbitrary epoch
+    /// that is fixed for the lifetime of the clock.
+    fn now_nanos(&self) -> u64;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now_nanos(&self) -> u64 {
+        (**self).now_nanos()
+    }
+}
+
+/// The default `Clock`, which measures monotonic time using `Instant`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now_nanos(&self) -> u64 {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+}
+
+/// A guard that records the time elapsed since its creation
+/// into a `Histo` when it is dropped.
+pub struct Timer<'a, C: Clock = MonotonicClock> {
+    histo: &'a Histo,
+    clock: C,
+    start: u64,
+}
+
+impl<'a, C: Clock> Timer<'a, C> {
+    pub(crate) fn new(histo: &'a Histo, clock: C) -> Timer<'a, C> {
+        let start = clock.now_nanos();
+        Timer {
+            histo,
+            clock,
+            start,
+        }
+    }
+}
+
+impl<'a, C: Clock> Drop for Timer<'a, C> {
+    fn drop(&mut self) {
+        let elapsed = self.clock.now_nanos().saturating_sub(self.start);
+        self.histo.measure_elapsed(elapsed);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn timer() {
+    use std::thread;
+    use std::time::Duration;
+
+    let c = Histo::default();
+    {
+        let _t = c.timer();
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(c.count(), 1);
+    assert!(c.percentile_duration(100.) >= Duration::from_millis(9));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn measure_timed() {
+    use std::thread;
+    use std::time::Duration;
+
+    let c = Histo::default();
+    let result = c.measure_timed(|| {
+        thread::sleep(Duration::from_millis(10));
+        42
+    });
+
+    assert_eq!(result, 42);
+    assert_eq!(c.count(), 1);
+    assert!(c.percentile_duration(100.) >= Duration::from_millis(9));
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn measure_timed_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let c = Histo::default();
+    let result = catch_unwind(AssertUnwindSafe(|| c.measure_timed(|| panic!("boom"))));
+    assert!(result.is_err());
+    assert_eq!(c.count(), 0);
+
+    // a Timer still records the time while unwinding
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let _t = c.timer();
+        panic!("boom");
+    }));
+    assert!(result.is_err());
+    assert_eq!(c.count(), 1);
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn synthetic_clock() {
+    use std::cell::Cell;
+
+    struct FakeClock(Cell<u64>);
+
+    impl Clock for FakeClock {
+        fn now_nanos(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    let clock = FakeClock(Cell::new(1_000));
+    let c = Histo::default();
+    let expected = Histo::default();
+    for _ in 0..10 {
+        let _t = c.timer_with(&clock);
+        clock.0.set(clock.0.get() + 5_000_000);
+    }
+    expected.measure_elapsed(5_000_000);
+
+    assert_eq!(c.count(), 10);
+    assert_eq!(c.sum(), 50_000_000);
+    assert_eq!(c.percentile(50.), expected.percentile(50.));
+    assert_eq!(c.percentile(100.), expected.percentile(100.));
+}
@@ -0,0 +1,99 @@
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// TopK retains the k largest raw values measured into a `Histo`. The
+// heap is behind a lock, so `threshold` caches its smallest value once
+// it is full, letting most measurements skip the lock entirely.
+#[derive(Debug)]
+pub(crate) struct TopK {
+    k: usize,
+    heap: Mutex<BinaryHeap<Reverse<Value>>>,
+    threshold: AtomicU64,
+}
+
+// Value orders f64s totally so that they can be kept in a heap.
+#[derive(Debug, Clone, Copy)]
+struct Value(f64);
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> CmpOrdering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Clone for TopK {
+    fn clone(&self) -> TopK {
+        let ret = TopK::new(self.k);
+        for value in self.values() {
+            ret.insert(value, 1);
+        }
+        ret
+    }
+}
+
+impl TopK {
+    pub(crate) fn new(k: usize) -> TopK {
+        TopK {
+            k,
+            heap: Mutex::new(BinaryHeap::with_capacity(k + 1)),
+            threshold: AtomicU64::new(f64::NEG_INFINITY.to_bits()),
+        }
+    }
+
+    // returns a TopK that retains as many values as this one
+    pub(crate) fn empty(&self) -> TopK {
+        TopK::new(self.k)
+    }
+
+    // records `n` occurrences of `value`
+    #[inline]
+    pub(crate) fn insert(&self, value: f64, n: usize) {
+        if value <= f64::from_bits(self.threshold.load(Ordering::Relaxed)) {
+            return;
+        }
+
+        let mut heap = self.heap.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..n.min(self.k) {
+            heap.push(Reverse(Value(value)));
+            if heap.len() > self.k {
+                heap.pop();
+            }
+        }
+        if heap.len() == self.k {
+            if let Some(Reverse(Value(smallest))) = heap.peek() {
+                self.threshold.store(smallest.to_bits(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    // returns the retained values in descending order
+    pub(crate) fn values(&self) -> Vec<f64> {
+        let heap = self.heap.lock().unwrap_or_else(|e| e.into_inner());
+        let mut values: Vec<f64> = heap.iter().map(|Reverse(Value(v))| *v).collect();
+        values.sort_by(|a, b| b.total_cmp(a));
+        values
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut heap = self.heap.lock().unwrap_or_else(|e| e.into_inner());
+        heap.clear();
+        self.threshold
+            .store(f64::NEG_INFINITY.to_bits(), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::Histo;
+
+/// A collector for a rolling window of recent measurements, held as a
+/// ring of `Histo`s that each cover one time slot. Measurements go into
+/// the current slot, and calling `rotate` on a timer advances to the
+/// next slot, discarding the oldest one. Reads merge every slot in the
+/// window.
+pub struct WindowedHisto {
+    slots: Vec<Histo>,
+    current: AtomicUsize,
+}
+
+impl WindowedHisto {
+    /// Create a `WindowedHisto` whose window spans the given number of
+    /// slots, including the current one.
+    pub fn new(slots: usize) -> WindowedHisto {
+        assert!(slots > 0, "a WindowedHisto requires at least one slot");
+
+        WindowedHisto {
+            slots: (0..slots).map(|_| Histo::default()).collect(),
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record a value into the current slot.
+    #[inline]
+    pub fn measure<T: Into<f64>>(&self, raw_value: T) -> usize {
+        self.slots[self.current.load(Ordering::Acquire)].measure(raw_value)
+    }
+
+    /// Advance to the next slot, clearing the measurements of the
+    /// oldest slot so that it can be reused as the current one. This
+    /// should only be called from a single thread, such as a timer.
+    /// Measurements that race with it may land in either slot.
+    pub fn rotate(&self) {
+        let next = (self.current.load(Ordering::Acquire) + 1) % self.slots.len();
+        self.slots[next].reset();
+        self.current.store(next, Ordering::Release);
+    }
+
+    /// Merge every slot in the window into a single `Histo`.
+    pub fn merged(&self) -> Histo {
+        let ret = Histo::default();
+        for slot in &self.slots {
+            ret.merge(slot);
+        }
+        ret
+    }
+
+    /// Retrieve a percentile [0-100] across the window. Returns NAN if
+    /// no metrics have been collected within it.
+    pub fn percentile(&self, p: f64) -> f64 {
+        self.merged().percentile(p)
+    }
+
+    /// Return the sum of all observations within the window.
+    pub fn sum(&self) -> u64 {
+        self.slots.iter().map(Histo::sum).sum()
+    }
+
+    /// Return the count of observations within the window.
+    pub fn count(&self) -> u64 {
+        self.slots.iter().map(Histo::count).sum()
+    }
+}
+
+#[test]
+#[cfg(not(feature = "disable"))]
+fn windowed() {
+    let window = WindowedHisto::new(3);
+    assert!(window.percentile(50.).is_nan());
+
+    for slot in 0..3 {
+        for _ in 0..100 {
+            window.measure((slot + 1) * 1000);
+        }
+        window.rotate();
+    }
+    // the first slot was cleared to become the current one
+    assert_eq!(window.count(), 200);
+    assert_eq!(window.percentile(0.), window.slots[1].percentile(0.));
+
+    for _ in 0..100 {
+        window.measure(10);
+    }
+    assert_eq!(window.count(), 300);
+    assert_eq!(window.percentile(0.), window.slots[0].percentile(0.));
+
+    window.rotate();
+    window.rotate();
+    assert_eq!(window.count(), 100);
+    assert_eq!(window.percentile(100.), window.slots[0].percentile(100.));
+    assert!(window.percentile(100.) < 11.);
+}